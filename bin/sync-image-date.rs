@@ -30,10 +30,19 @@ fn find_video(path: &Path) -> Result<PathBuf> {
     bail!("video not found")
 }
 
+/// 优先使用图片中写入的来源，没有时按文件名查找
+fn source_video(path: &Path) -> Result<PathBuf> {
+    let buf = std::fs::read(path).context("read image failed")?;
+    match screenshot::embed::read(&buf) {
+        Some(source) if source.path.exists() => Ok(source.path),
+        _ => find_video(path),
+    }
+}
+
 fn main() -> Result<()> {
     let f = std::env::args().nth(1).context("Missing input")?;
     let f = std::path::Path::new(&f);
-    let source = find_video(f)?;
+    let source = source_video(f)?;
 
     let meta = std::fs::metadata(&source)?;
     filetime::set_file_mtime(f, FileTime::from_last_modification_time(&meta))?;
@@ -0,0 +1,125 @@
+//! 把截图轮播做成动图，用于网页上的预览
+
+use anyhow::{bail, Context as _, Result};
+use ffmpeg_next::Rational;
+use opencv::{core::Mat, prelude::*};
+
+use crate::{
+    encode::{EncodeSettings, VideoWriter},
+    utils::{TempDir, VideoDuration},
+};
+
+/// 动图的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Gif,
+    WebP,
+    Apng,
+}
+
+impl AnimationFormat {
+    /// 输出文件的扩展名
+    pub fn ext(self) -> &'static str {
+        match self {
+            Self::Gif => "gif",
+            Self::WebP => "webp",
+            Self::Apng => "apng",
+        }
+    }
+
+    fn settings(self) -> (&'static str, &'static str, &'static str) {
+        // (muxer, codec, filter)
+        match self {
+            // 所有帧共用一个调色板，palettegen 会在输入结束后才输出
+            Self::Gif => (
+                "gif",
+                "gif",
+                "split[a][b];[a]palettegen=stats_mode=full[p];[b][p]paletteuse",
+            ),
+            Self::WebP => ("webp", "libwebp_anim", "format=yuv420p"),
+            Self::Apng => ("apng", "apng", "format=rgb24"),
+        }
+    }
+}
+
+impl std::str::FromStr for AnimationFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_lowercase().as_str() {
+            "gif" => Self::Gif,
+            "webp" => Self::WebP,
+            "apng" | "png" => Self::Apng,
+            _ => bail!("unknown animation format {}", s),
+        })
+    }
+}
+
+/// 动图的选项
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnimationOptions {
+    pub format: AnimationFormat,
+    /// 每张截图显示的时间 (毫秒)
+    pub frame_duration_ms: u32,
+}
+
+impl Default for AnimationOptions {
+    fn default() -> Self {
+        Self {
+            format: AnimationFormat::Gif,
+            frame_duration_ms: 500,
+        }
+    }
+}
+
+/// 把截图依次编码为动图，返回编码后的数据
+pub fn encode_animation(
+    images: &[(Mat, VideoDuration)],
+    options: &AnimationOptions,
+) -> Result<Vec<u8>> {
+    let Some((first, _)) = images.first() else { bail!("没有截图生成") };
+    let (width, height) = (first.cols() as u32, first.rows() as u32);
+    let (muxer, codec, filter) = options.format.settings();
+    let settings = EncodeSettings {
+        muxer,
+        codec,
+        filter,
+        codec_options: &[],
+        width,
+        height,
+        time_base: Rational::new(1, 1000),
+    };
+
+    // ffmpeg 的 muxer 需要写到文件里
+    let dir = TempDir::new()?;
+    let path = dir.join(format!("preview.{}", options.format.ext()));
+    let mut writer = VideoWriter::create(&path, &settings)?;
+    for (i, (image, _)) in images.iter().enumerate() {
+        if (image.cols() as u32, image.rows() as u32) != (width, height) {
+            bail!("截图尺寸不一致");
+        }
+        let pts = i as i64 * options.frame_duration_ms as i64;
+        writer.write(image.data_bytes()?, width as usize * 3, pts)?;
+    }
+    writer.finish()?;
+    std::fs::read(&path).context("read animation failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_format() {
+        assert_eq!(
+            "GIF".parse::<AnimationFormat>().unwrap(),
+            AnimationFormat::Gif
+        );
+        assert_eq!(
+            "png".parse::<AnimationFormat>().unwrap(),
+            AnimationFormat::Apng
+        );
+        assert_eq!(AnimationFormat::Apng.ext(), "apng");
+        assert!("mp4".parse::<AnimationFormat>().is_err());
+    }
+}
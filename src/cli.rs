@@ -1,30 +1,207 @@
 use anyhow::{Context, Result};
 use clap::Parser;
+use ffmpeg_next::Rational;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use screenshot::{
+    parse_duration, AnimationFormat, AnimationOptions, Deinterlace, ExtractOptions, FrameSelection,
+    QualityFilter, ScalingAlgorithm, SheetLayout, SpriteOptions, TeaserFormat, TeaserOptions,
+    ToneMapping,
+};
 use std::path::{Path, PathBuf};
 
+use crate::{config::Config, output, walk::WalkOptions};
+
 #[derive(Debug, Parser)]
 #[command(author = env!("CARGO_PKG_AUTHORS"), version = env!("CARGO_PKG_VERSION"), about = "生成视频截图")]
 pub struct Args {
-    #[clap(short, long, default_value = "5", help = "纵向数量")]
-    pub rows: u32,
-    #[clap(short, long, default_value = "3", help = "横向数量")]
-    pub cols: u32,
-    #[clap(short, long, default_value = "2048", help = "横向尺寸")]
-    pub width: u32,
+    #[clap(short, long, help = "纵向数量 [默认: 5]")]
+    pub rows: Option<u32>,
+    #[clap(short, long, help = "横向数量 [默认: 3]")]
+    pub cols: Option<u32>,
+    #[clap(short, long, help = "横向尺寸 [默认: 2048]")]
+    pub width: Option<u32>,
+
+    #[clap(short, long, help = "图片之间的间隔 [默认: 10]")]
+    pub space: Option<u32>,
 
-    #[clap(short, long, default_value = "10", help = "图片之间的间隔")]
-    pub space: u32,
+    #[clap(long, help = "输出文件扩展名 [默认: jpg]")]
+    pub ext: Option<String>,
 
-    #[clap(long, default_value = "jpg", help = "输出文件扩展名")]
-    pub ext: String,
+    #[clap(
+        long,
+        help = "用户配置文件路径，默认读取 ~/.config/screenshot/config.toml。文件夹下的 .screenshot.toml 会覆盖用户配置"
+    )]
+    pub config: Option<PathBuf>,
 
     #[cfg(feature = "font")]
     #[clap(long, short, help = "手动指定使用字体的路径")]
     pub font: Option<PathBuf>,
 
+    #[clap(
+        long,
+        help = "根据画面变化选择截图：每个位置附近解码 N 帧候选，选择与上一张差异最大的一帧"
+    )]
+    pub scene_candidates: Option<u32>,
+
+    #[clap(
+        long,
+        value_delimiter = ',',
+        value_parser = parse_duration,
+        help = "指定截图的时间点，用逗号分隔，如 00:01:30,05:00,600"
+    )]
+    pub at: Vec<Rational>,
+    #[clap(long, value_parser = parse_duration, help = "截图范围的开始，如 01:30 跳过片头")]
+    pub start: Option<Rational>,
+    #[clap(
+        long,
+        value_parser = parse_duration,
+        allow_hyphen_values = true,
+        help = "截图范围的结束，负数表示距离结尾的时间，如 -03:00 跳过片尾"
+    )]
+    pub end: Option<Rational>,
+
+    #[clap(
+        long,
+        default_value = "10",
+        help = "平均亮度低于此值 (0-255) 的截图视为黑屏"
+    )]
+    pub min_brightness: f32,
+    #[clap(
+        long,
+        default_value = "5",
+        help = "亮度标准差低于此值的截图视为纯色画面"
+    )]
+    pub min_contrast: f32,
+    #[clap(
+        long,
+        default_value = "0",
+        help = "拉普拉斯方差低于此值的截图视为模糊画面，0 表示不检查"
+    )]
+    pub min_sharpness: f32,
+    #[clap(
+        long,
+        default_value = "3",
+        help = "截图不合格时在附近重试的次数，0 表示不检查截图质量"
+    )]
+    pub quality_retries: u32,
+
+    #[clap(long, default_value = "0", help = "每个视频解码器的线程数，0 表示自动")]
+    pub threads: usize,
+    #[clap(
+        long,
+        default_value = "1",
+        help = "每个视频同时截图的数量，每个任务单独打开一次视频"
+    )]
+    pub parallel: usize,
+
+    #[clap(
+        long,
+        default_value = "area",
+        help = "缩放算法：fast-bilinear, bilinear, bicubic, area, lanczos, spline"
+    )]
+    pub scaler: ScalingAlgorithm,
+    #[clap(
+        long,
+        default_value = "bt2390",
+        help = "HDR 视频的色调映射算法：none, reinhard, hable, bt2390"
+    )]
+    pub tonemap: ToneMapping,
+    #[clap(long, help = "HDR 视频的峰值亮度 (nits) [默认: 1000]")]
+    pub hdr_peak: Option<f32>,
+    #[clap(
+        long,
+        default_value = "auto",
+        help = "隔行扫描视频去隔行：off, auto, force"
+    )]
+    pub deinterlace: Deinterlace,
+    #[clap(
+        long,
+        help = "解码后、缩放前使用的 ffmpeg filter graph，如 yadif,eq=brightness=0.05"
+    )]
+    pub filter: Option<String>,
+
+    #[clap(
+        long,
+        help = "同时生成截图轮播的动图：gif, webp, apng，保存为 <视频>.anim.<格式>"
+    )]
+    pub animation: Option<AnimationFormat>,
+    #[clap(long, default_value = "500", help = "动图中每张截图显示的毫秒数")]
+    pub animation_frame_duration: u32,
+
+    #[clap(
+        long,
+        help = "同时生成由每个截图位置的片段组成的预告片：mp4, webm，保存为 <视频>.teaser.<格式>"
+    )]
+    pub teaser: Option<TeaserFormat>,
+    #[clap(
+        long,
+        default_value = "1",
+        value_parser = parse_duration,
+        help = "预告片中每个片段的长度"
+    )]
+    pub teaser_segment_length: Rational,
+
+    #[clap(
+        long,
+        help = "把每张截图单独保存到这个文件夹，处理文件夹时保持相同的目录结构，文件名为 <视频名>-<时间>.<格式>"
+    )]
+    pub export_frames: Option<PathBuf>,
+    #[clap(
+        long,
+        default_value = "jpg",
+        help = "单独保存截图的格式：jpg, png, webp"
+    )]
+    pub frames_ext: String,
+    #[clap(long, help = "单独保存的截图不缩小，使用视频原始尺寸")]
+    pub full_resolution: bool,
+    #[clap(
+        long,
+        help = "同时生成网页播放器进度条预览用的雪碧图 <视频>.sprite.<格式> 和 <视频>.vtt"
+    )]
+    pub sprite: bool,
+    #[clap(
+        long,
+        default_value = "10",
+        value_parser = parse_duration,
+        help = "雪碧图截图的间隔"
+    )]
+    pub sprite_interval: Rational,
+    #[clap(long, default_value = "160", help = "雪碧图中每张截图的宽度")]
+    pub sprite_width: u32,
+    #[clap(long, default_value = "10", help = "雪碧图每行的截图数量")]
+    pub sprite_cols: u32,
+
+    #[clap(long, help = "不生成拼接的截图，只生成其他输出")]
+    pub no_sheet: bool,
+    #[clap(
+        long,
+        help = "同时生成 <视频>.json，记录视频信息、排版和每张截图的位置和时间"
+    )]
+    pub json: bool,
+    #[clap(long, help = "不在截图中写入视频路径、大小、修改时间等来源信息")]
+    pub no_metadata: bool,
+
     // flags
+    #[clap(
+        long,
+        help = "精确定位：从前一个关键帧解码到指定的时间点，更慢但时间更准确"
+    )]
+    pub accurate_seek: bool,
+    #[clap(long, help = "保留截图四周的黑边，默认会自动裁掉")]
+    pub keep_black_bars: bool,
+
     #[clap(long, help = "输出文件去掉视频扩展名")]
     pub remove_ext: bool,
+    #[clap(
+        long,
+        help = "输出到这个文件夹，处理文件夹时保持相同的目录结构。默认保存在视频旁边"
+    )]
+    pub output_dir: Option<PathBuf>,
+    #[clap(
+        long,
+        help = "输出文件名模板，必须包含 {ext}，还可用 {name} {stem} {rows} {cols} {width} {hash}，如 {stem}_{rows}x{cols}.{ext} [默认: {name}.{ext}]"
+    )]
+    pub name_template: Option<String>,
 
     #[cfg(target_os = "windows")]
     #[clap(long, help = "是否用一个窗口显示")]
@@ -35,6 +212,41 @@ pub struct Args {
 
     #[clap(long, help = "在处理文件夹时，不进行报错，而是跳过")]
     pub ignore_error: bool,
+    #[clap(
+        long,
+        short,
+        default_value = "0",
+        help = "处理文件夹时同时处理的视频数量，0 表示 CPU 核数"
+    )]
+    pub jobs: usize,
+    #[clap(
+        long,
+        help = "处理文件夹时最多进入的子文件夹层数，0 表示只处理这个文件夹中的视频"
+    )]
+    pub max_depth: Option<usize>,
+    #[clap(long, help = "处理文件夹时跟随符号链接，默认跳过")]
+    pub follow_symlinks: bool,
+    #[clap(
+        long,
+        help = "处理文件夹时只处理相对路径匹配这个 glob 的文件，可以指定多次，如 '**/*.mkv'"
+    )]
+    pub include: Vec<String>,
+    #[clap(
+        long,
+        help = "处理文件夹时跳过相对路径匹配这个 glob 的文件和文件夹，可以指定多次，如 '*sample*'"
+    )]
+    pub exclude: Vec<String>,
+    #[clap(
+        long,
+        value_delimiter = ',',
+        help = "处理文件夹时当作视频的扩展名，用逗号分隔，如 mp4,mkv,iso [默认: 常见的视频扩展名]"
+    )]
+    pub extensions: Vec<String>,
+    #[clap(
+        long,
+        help = "处理文件夹时用 ffmpeg 打开每个文件判断是否是视频，不检查扩展名，但跳过截图、动图等生成的文件"
+    )]
+    pub probe: bool,
 
     #[clap(
         long,
@@ -44,29 +256,136 @@ pub struct Args {
 
     #[clap(long, help = "默认会覆盖已存文件，使用此选项可以禁用此功能")]
     pub no_overwrite: bool,
+    #[clap(
+        long,
+        help = "只在视频的大小、修改时间或截图参数和已有截图中记录的不同时重新生成"
+    )]
+    pub update: bool,
+    #[clap(
+        long,
+        help = "记录每个视频处理结果的清单文件 (JSON lines)，视频和参数没有变化时直接跳过"
+    )]
+    pub manifest: Option<PathBuf>,
+    #[clap(long, help = "重新处理清单中记录失败的视频，默认跳过")]
+    pub retry_failed: bool,
 
     #[clap(help = "视频路径")]
     pub input: PathBuf,
 }
 
 impl Args {
-    pub fn num_of_frames(&self) -> u32 {
-        self.rows * self.cols
+    /// 命令行中指定了的参数，优先级高于配置文件
+    pub fn overrides(&self) -> Config {
+        Config {
+            rows: self.rows,
+            cols: self.cols,
+            width: self.width,
+            space: self.space,
+            ext: self.ext.clone(),
+            #[cfg(feature = "font")]
+            font: self.font.clone(),
+            #[cfg(not(feature = "font"))]
+            font: None,
+            auto_flip: self.no_auto_flip.then_some(false),
+            overwrite: self.no_overwrite.then_some(false),
+            extensions: (!self.extensions.is_empty()).then(|| self.extensions.clone()),
+        }
+    }
+    pub fn extract_options(&self) -> ExtractOptions {
+        let selection = match self.scene_candidates {
+            Some(candidates) if candidates > 1 => FrameSelection::SceneChange { candidates },
+            _ => FrameSelection::Uniform,
+        };
+        let quality = (self.quality_retries > 0).then(|| QualityFilter {
+            min_brightness: self.min_brightness,
+            min_contrast: self.min_contrast,
+            min_sharpness: self.min_sharpness,
+            retries: self.quality_retries,
+        });
+        ExtractOptions {
+            selection,
+            quality,
+            timestamps: self.at.clone(),
+            start: self.start,
+            end: self.end,
+            accurate_seek: self.accurate_seek,
+            decoder_threads: self.threads,
+            parallel: self.parallel,
+            scaling: self.scaler,
+            tone_mapping: self.tonemap,
+            hdr_peak: self.hdr_peak,
+            keep_black_bars: self.keep_black_bars,
+            deinterlace: self.deinterlace,
+            filter: self.filter.clone(),
+            // 只有单独保存的截图使用原始尺寸，见 `process::export_frames`
+            full_resolution: false,
+        }
     }
-    pub fn scaled_frame_width(&self) -> u32 {
-        (self.width - (self.cols + 1) * self.space) / self.cols
+    pub fn animation_options(&self) -> Option<AnimationOptions> {
+        Some(AnimationOptions {
+            format: self.animation?,
+            frame_duration_ms: self.animation_frame_duration,
+        })
     }
-    pub fn output_name(&self, input: &Path) -> Result<PathBuf> {
-        if self.remove_ext {
-            Ok(input.with_extension(&self.ext))
+    pub fn teaser_options(&self) -> Option<TeaserOptions> {
+        Some(TeaserOptions {
+            format: self.teaser?,
+            segment_length: self.teaser_segment_length,
+        })
+    }
+    pub fn sprite_options(&self) -> Option<SpriteOptions> {
+        self.sprite.then(|| SpriteOptions {
+            interval: self.sprite_interval,
+            tile_width: self.sprite_width,
+            cols: self.sprite_cols,
+        })
+    }
+    pub fn walk_options(&self) -> Result<WalkOptions> {
+        let include = if self.include.is_empty() {
+            None
         } else {
-            // append ext to filename
-            let filename = input.file_name().context("input filename missing")?;
-            let mut filename = filename.to_owned();
-            filename.push(".");
-            filename.push(&self.ext);
+            Some(glob_set(&self.include)?)
+        };
+        Ok(WalkOptions {
+            max_depth: self.max_depth,
+            follow_symlinks: self.follow_symlinks,
+            include,
+            exclude: glob_set(&self.exclude)?,
+        })
+    }
+    /// 单独保存截图的文件夹，处理文件夹时保持相同的目录结构，避免不同文件夹中同名的视频互相覆盖
+    pub fn export_dir(&self, input: &Path) -> Option<PathBuf> {
+        let export_dir = self.export_frames.as_ref()?;
+        let dir = input.parent().unwrap_or(Path::new(""));
+        let relative = dir.strip_prefix(&self.input).unwrap_or(Path::new(""));
+        Some(export_dir.join(relative))
+    }
 
-            Ok(input.with_file_name(filename))
+    /// 输出文件的路径，`ext` 是输出文件的扩展名
+    pub fn output_name(&self, input: &Path, layout: &SheetLayout, ext: &str) -> Result<PathBuf> {
+        let template = match (&self.name_template, self.remove_ext) {
+            (Some(template), _) => template.as_str(),
+            (None, true) => output::REMOVE_EXT_TEMPLATE,
+            (None, false) => output::DEFAULT_TEMPLATE,
+        };
+        let filename = output::render(template, input, layout, ext)?;
+        let dir = input.parent().context("input parent missing")?;
+        match &self.output_dir {
+            // 保持视频相对于输入文件夹的目录结构
+            Some(output_dir) => {
+                let relative = dir.strip_prefix(&self.input).unwrap_or(Path::new(""));
+                Ok(output_dir.join(relative).join(filename))
+            }
+            None => Ok(dir.join(filename)),
         }
     }
 }
+
+fn glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern).with_context(|| format!("无效的 glob: {}", pattern))?;
+        builder.add(glob);
+    }
+    builder.build().context("build glob set failed")
+}
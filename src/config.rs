@@ -0,0 +1,170 @@
+use anyhow::{Context, Result};
+use screenshot::{ContactSheet, RenderOptions, SheetLayout};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+/// 每个文件夹下可以放一个配置文件，覆盖上层的配置
+pub const DIR_CONFIG_NAME: &str = ".screenshot.toml";
+
+/// 配置文件，所有字段都是可选的。
+///
+/// 优先级：命令行参数 > 文件夹配置（越深越优先） > 用户配置 > 默认值
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub rows: Option<u32>,
+    pub cols: Option<u32>,
+    pub width: Option<u32>,
+    pub space: Option<u32>,
+    pub ext: Option<String>,
+    pub font: Option<PathBuf>,
+    pub auto_flip: Option<bool>,
+    pub overwrite: Option<bool>,
+    /// 处理文件夹时当作视频的扩展名，不区分大小写
+    pub extensions: Option<Vec<String>>,
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        debug!("loading config {}", path.display());
+        let s = std::fs::read_to_string(path)
+            .with_context(|| format!("read config {} failed", path.display()))?;
+        let mut config: Self = toml::from_str(&s)
+            .with_context(|| format!("parse config {} failed", path.display()))?;
+        // 字体的相对路径相对于配置文件所在的文件夹
+        if let (Some(font), Some(dir)) = (config.font.as_mut(), path.parent()) {
+            if font.is_relative() {
+                *font = dir.join(&*font);
+            }
+        }
+        Ok(config)
+    }
+
+    /// 用户配置文件的默认位置
+    pub fn user_config_path() -> Option<PathBuf> {
+        #[cfg(windows)]
+        let dir = std::env::var_os("APPDATA").map(PathBuf::from);
+        #[cfg(not(windows))]
+        let dir = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+        dir.map(|dir| dir.join("screenshot").join("config.toml"))
+    }
+
+    /// 读取用户配置。指定了路径时文件必须存在，否则不存在就使用默认配置
+    pub fn user(path: Option<&Path>) -> Result<Self> {
+        match path {
+            Some(path) => Self::load(path),
+            None => match Self::user_config_path() {
+                Some(path) if path.is_file() => Self::load(&path),
+                _ => Ok(Self::default()),
+            },
+        }
+    }
+
+    /// 如果 `dir` 下有配置文件，返回合并后的配置
+    pub fn for_dir(&self, dir: &Path) -> Result<Self> {
+        let path = dir.join(DIR_CONFIG_NAME);
+        if !path.is_file() {
+            return Ok(self.clone());
+        }
+        Ok(self.clone().merge(Self::load(&path)?))
+    }
+
+    /// `other` 中设置了的字段覆盖 `self`
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            rows: other.rows.or(self.rows),
+            cols: other.cols.or(self.cols),
+            width: other.width.or(self.width),
+            space: other.space.or(self.space),
+            ext: other.ext.or(self.ext),
+            font: other.font.or(self.font),
+            auto_flip: other.auto_flip.or(self.auto_flip),
+            overwrite: other.overwrite.or(self.overwrite),
+            extensions: other.extensions.or(self.extensions),
+        }
+    }
+
+    pub fn layout(&self) -> SheetLayout {
+        let default = SheetLayout::default();
+        SheetLayout {
+            rows: self.rows.unwrap_or(default.rows),
+            cols: self.cols.unwrap_or(default.cols),
+            width: self.width.unwrap_or(default.width),
+            space: self.space.unwrap_or(default.space),
+            auto_flip: self.auto_flip.unwrap_or(default.auto_flip),
+        }
+    }
+
+    pub fn render_options(&self) -> RenderOptions {
+        let default = RenderOptions::default();
+        RenderOptions {
+            ext: self.ext.clone().unwrap_or(default.ext),
+            #[cfg(feature = "font")]
+            font: self.font.clone().or(default.font),
+        }
+    }
+
+    pub fn sheet(&self) -> ContactSheet {
+        ContactSheet::with(self.layout(), self.render_options())
+    }
+
+    /// 默认覆盖已存在的文件
+    pub fn overwrite(&self) -> bool {
+        self.overwrite.unwrap_or(true)
+    }
+
+    /// 扩展名是否是视频，没有配置时使用常见的视频扩展名
+    pub fn is_video_ext(&self, ext: &str) -> bool {
+        let ext = ext.to_lowercase();
+        match &self.extensions {
+            Some(extensions) => extensions
+                .iter()
+                .any(|e| e.trim_start_matches('.').to_lowercase() == ext),
+            None => DEFAULT_EXTENSIONS.contains(&ext.as_str()),
+        }
+    }
+}
+
+const DEFAULT_EXTENSIONS: &[&str] = &[
+    "mp4", "m4v", "mkv", "avi", "webm", "mov", "flv", "ts", "wmv", "mpg", "mpeg", "m2ts", "mts",
+    "3gp", "vob", "rm", "rmvb", "ogv", "asf", "f4v", "divx",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_config() {
+        let user: Config = toml::from_str("rows = 4\ncols = 4\next = \"png\"").unwrap();
+        let dir: Config = toml::from_str("cols = 6\noverwrite = false").unwrap();
+        let cli = Config {
+            rows: Some(8),
+            ..Default::default()
+        };
+        let config = user.merge(dir).merge(cli);
+        let layout = config.layout();
+        assert_eq!((layout.rows, layout.cols), (8, 6));
+        assert_eq!(layout.width, SheetLayout::default().width);
+        assert_eq!(config.render_options().ext, "png");
+        assert!(!config.overwrite());
+    }
+
+    #[test]
+    fn video_extensions() {
+        let config = Config::default();
+        assert!(config.is_video_ext("MKV"));
+        assert!(config.is_video_ext("rmvb"));
+        assert!(!config.is_video_ext("jpg"));
+        let config: Config = toml::from_str("extensions = [\".mp4\", \"ISO\"]").unwrap();
+        assert!(config.is_video_ext("iso"));
+        assert!(!config.is_video_ext("mkv"));
+    }
+
+    #[test]
+    fn unknown_field() {
+        assert!(toml::from_str::<Config>("row = 4").is_err());
+    }
+}
@@ -0,0 +1,403 @@
+//! 把来源视频的信息写入生成的图片：JPEG 和 WebP 使用 XMP，PNG 使用文本块
+
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+
+/// 生成图片时的来源信息
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceInfo {
+    /// 视频的绝对路径
+    pub path: PathBuf,
+    /// 视频的文件大小
+    pub size: u64,
+    /// 视频的修改时间 (Unix 时间戳，秒)
+    pub modified: i64,
+    /// 视频时长 (秒)
+    pub duration: Option<f64>,
+    /// 生成时使用的参数
+    pub settings: String,
+}
+
+const KEYS: [&str; 6] = [
+    "Source",
+    "SourceSize",
+    "SourceModified",
+    "Duration",
+    "Settings",
+    "Software",
+];
+
+const XMP_NAMESPACE: &str = "urn:screenshot:source:1.0";
+const XMP_JPEG_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+impl SourceInfo {
+    /// 读取视频文件的路径、大小和修改时间
+    pub fn from_file(path: &Path, duration: Option<f64>, settings: String) -> Result<Self> {
+        let meta = std::fs::metadata(path).context("read source metadata failed")?;
+        let modified = filetime::FileTime::from_last_modification_time(&meta).unix_seconds();
+        Ok(Self {
+            path: std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+            size: meta.len(),
+            modified,
+            duration,
+            settings,
+        })
+    }
+
+    /// 视频的大小、修改时间和截图参数都相同，路径可以不同
+    pub fn same_source(&self, other: &SourceInfo) -> bool {
+        (self.size, self.modified, &self.settings) == (other.size, other.modified, &other.settings)
+    }
+
+    fn entries(&self) -> Vec<(&'static str, String)> {
+        let mut entries = vec![
+            ("Source", self.path.to_string_lossy().to_string()),
+            ("SourceSize", self.size.to_string()),
+            ("SourceModified", self.modified.to_string()),
+        ];
+        if let Some(duration) = self.duration {
+            entries.push(("Duration", format!("{:.3}", duration)));
+        }
+        entries.push(("Settings", self.settings.clone()));
+        entries.push((
+            "Software",
+            concat!("screenshot ", env!("CARGO_PKG_VERSION")).to_string(),
+        ));
+        entries
+    }
+
+    fn from_entries(entries: &[(String, String)]) -> Option<Self> {
+        let get = |key: &str| {
+            entries
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v.as_str())
+        };
+        Some(Self {
+            path: PathBuf::from(get("Source")?),
+            size: get("SourceSize")?.parse().ok()?,
+            modified: get("SourceModified")?.parse().ok()?,
+            duration: get("Duration").and_then(|d| d.parse().ok()),
+            settings: get("Settings").unwrap_or_default().to_string(),
+        })
+    }
+}
+
+/// 按 `ext` 把来源信息写入编码后的图片，不支持的格式原样返回
+pub fn embed(buf: Vec<u8>, ext: &str, source: &SourceInfo) -> Result<Vec<u8>> {
+    let entries = source.entries();
+    match ext.to_lowercase().as_str() {
+        "png" => embed_png(buf, &entries),
+        "jpg" | "jpeg" => embed_jpeg(buf, &xmp(&entries)),
+        "webp" => embed_webp(buf, &xmp(&entries)),
+        _ => {
+            debug!("embedding metadata into {} is not supported", ext);
+            Ok(buf)
+        }
+    }
+}
+
+/// 读取 `embed` 写入的来源信息
+pub fn read(buf: &[u8]) -> Option<SourceInfo> {
+    let entries = if buf.starts_with(PNG_SIGNATURE) {
+        read_png(buf)
+    } else if buf.starts_with(&[0xFF, 0xD8]) {
+        read_xmp(find_jpeg_xmp(buf)?)
+    } else if buf.len() >= 12 && &buf[..4] == b"RIFF" && &buf[8..12] == b"WEBP" {
+        read_xmp(webp_chunks(buf).find(|(tag, _)| tag == b"XMP ")?.1)
+    } else {
+        return None;
+    };
+    SourceInfo::from_entries(&entries)
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// 在 IHDR 之后插入文本块，只有 ASCII 的值用 tEXt，否则用 UTF-8 的 iTXt
+fn embed_png(buf: Vec<u8>, entries: &[(&str, String)]) -> Result<Vec<u8>> {
+    if !buf.starts_with(PNG_SIGNATURE) || buf.len() < 33 || &buf[12..16] != b"IHDR" {
+        bail!("invalid png data");
+    }
+    // 签名 8 字节 + IHDR 25 字节
+    let (head, rest) = buf.split_at(33);
+    let mut out = head.to_vec();
+    for (key, value) in entries {
+        let mut data = key.as_bytes().to_vec();
+        data.push(0);
+        let tag = if value.is_ascii() {
+            b"tEXt"
+        } else {
+            // 不压缩，没有语言标签和翻译的关键字
+            data.extend_from_slice(&[0, 0, 0, 0]);
+            b"iTXt"
+        };
+        data.extend_from_slice(value.as_bytes());
+        out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = out.len();
+        out.extend_from_slice(tag);
+        out.extend_from_slice(&data);
+        let crc = crc32(&out[start..]);
+        out.extend_from_slice(&crc.to_be_bytes());
+    }
+    out.extend_from_slice(rest);
+    Ok(out)
+}
+
+fn read_png(buf: &[u8]) -> Vec<(String, String)> {
+    let mut entries = vec![];
+    let mut pos = PNG_SIGNATURE.len();
+    while pos + 8 <= buf.len() {
+        let len = u32::from_be_bytes(buf[pos..pos + 4].try_into().unwrap()) as usize;
+        let tag = &buf[pos + 4..pos + 8];
+        let Some(data) = buf.get(pos + 8..pos + 8 + len) else { break; };
+        if tag == b"IEND" {
+            break;
+        }
+        entries.extend(png_text(tag, data));
+        pos += 12 + len;
+    }
+    entries
+}
+
+/// 解析 tEXt 和未压缩的 iTXt，返回 (关键字, 文本)
+fn png_text(tag: &[u8], data: &[u8]) -> Option<(String, String)> {
+    let (key, rest) = data.split_at(data.iter().position(|&b| b == 0)?);
+    let text = match tag {
+        b"tEXt" => &rest[1..],
+        // 压缩标志、压缩方法、语言标签、翻译的关键字之后是文本
+        b"iTXt" if rest.get(1..3)? == [0, 0] => rest[3..].splitn(3, |&b| b == 0).nth(2)?,
+        _ => return None,
+    };
+    Some((
+        String::from_utf8_lossy(key).to_string(),
+        String::from_utf8_lossy(text).to_string(),
+    ))
+}
+
+/// 在 SOI 和 JFIF 的 APP0 之后插入 APP1 XMP 段
+fn embed_jpeg(buf: Vec<u8>, xmp: &str) -> Result<Vec<u8>> {
+    if !buf.starts_with(&[0xFF, 0xD8]) {
+        bail!("invalid jpeg data");
+    }
+    let len = 2 + XMP_JPEG_HEADER.len() + xmp.len();
+    if len > u16::MAX as usize {
+        bail!("xmp too large for jpeg: {} bytes", xmp.len());
+    }
+    let mut pos = 2;
+    while buf.get(pos..pos + 2) == Some(&[0xFF, 0xE0][..]) {
+        let Some(seg) = buf.get(pos + 2..pos + 4) else { break; };
+        pos += 2 + u16::from_be_bytes([seg[0], seg[1]]) as usize;
+    }
+    let pos = pos.min(buf.len());
+    let mut out = Vec::with_capacity(buf.len() + len + 2);
+    out.extend_from_slice(&buf[..pos]);
+    out.extend_from_slice(&[0xFF, 0xE1]);
+    out.extend_from_slice(&(len as u16).to_be_bytes());
+    out.extend_from_slice(XMP_JPEG_HEADER);
+    out.extend_from_slice(xmp.as_bytes());
+    out.extend_from_slice(&buf[pos..]);
+    Ok(out)
+}
+
+fn find_jpeg_xmp(buf: &[u8]) -> Option<&[u8]> {
+    let mut pos = 2;
+    // 只看图像数据之前的段
+    while buf.get(pos) == Some(&0xFF) && buf.get(pos + 1) != Some(&0xDA) {
+        let marker = *buf.get(pos + 1)?;
+        let len = u16::from_be_bytes([*buf.get(pos + 2)?, *buf.get(pos + 3)?]) as usize;
+        let data = buf.get(pos + 4..pos + 2 + len)?;
+        if marker == 0xE1 && data.starts_with(XMP_JPEG_HEADER) {
+            return Some(&data[XMP_JPEG_HEADER.len()..]);
+        }
+        pos += 2 + len;
+    }
+    None
+}
+
+/// 简单格式的 WebP 需要先转换为带 VP8X 的扩展格式，再在末尾加上 XMP 块
+fn embed_webp(buf: Vec<u8>, xmp: &str) -> Result<Vec<u8>> {
+    if buf.len() < 12 || &buf[..4] != b"RIFF" || &buf[8..12] != b"WEBP" {
+        bail!("invalid webp data");
+    }
+    let mut out = buf[..12].to_vec();
+    let Some((tag, data)) = webp_chunks(&buf).next() else { bail!("invalid webp data") };
+    if tag == b"VP8X" {
+        out.extend_from_slice(&buf[12..]);
+        // XMP 标志
+        out[20] |= 0x04;
+    } else {
+        let (width, height) = webp_size(tag, data).context("invalid webp data")?;
+        let mut vp8x = vec![0x04, 0, 0, 0];
+        vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        push_riff_chunk(&mut out, b"VP8X", &vp8x);
+        out.extend_from_slice(&buf[12..]);
+    }
+    push_riff_chunk(&mut out, b"XMP ", xmp.as_bytes());
+    let riff_size = (out.len() - 8) as u32;
+    out[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(out)
+}
+
+fn push_riff_chunk(out: &mut Vec<u8>, tag: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(tag);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+fn webp_chunks(buf: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut pos = 12;
+    std::iter::from_fn(move || {
+        let tag = buf.get(pos..pos + 4)?;
+        let len = u32::from_le_bytes(buf.get(pos + 4..pos + 8)?.try_into().unwrap()) as usize;
+        let data = buf.get(pos + 8..pos + 8 + len)?;
+        pos += 8 + len + len % 2;
+        Some((tag, data))
+    })
+}
+
+/// 从有损 (VP8) 或无损 (VP8L) 的图像数据中读取宽高
+fn webp_size(tag: &[u8], data: &[u8]) -> Option<(u32, u32)> {
+    match tag {
+        b"VP8 " if data.get(3..6)? == [0x9D, 0x01, 0x2A] => {
+            let w = u16::from_le_bytes([*data.get(6)?, *data.get(7)?]) & 0x3FFF;
+            let h = u16::from_le_bytes([*data.get(8)?, *data.get(9)?]) & 0x3FFF;
+            Some((w as u32, h as u32))
+        }
+        b"VP8L" if data.first() == Some(&0x2F) => {
+            let bits = u32::from_le_bytes(data.get(1..5)?.try_into().ok()?);
+            Some(((bits & 0x3FFF) + 1, ((bits >> 14) & 0x3FFF) + 1))
+        }
+        _ => None,
+    }
+}
+
+fn xmp(entries: &[(&str, String)]) -> String {
+    let mut attrs = String::new();
+    for (key, value) in entries {
+        attrs.push_str(&format!(
+            "\n    screenshot:{}=\"{}\"",
+            key,
+            xml_escape(value)
+        ));
+    }
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n\
+         <rdf:Description rdf:about=\"\"\n    xmlns:screenshot=\"{}\"{}/>\n\
+         </rdf:RDF>\n\
+         </x:xmpmeta>\n\
+         <?xpacket end=\"r\"?>",
+        XMP_NAMESPACE, attrs
+    )
+}
+
+fn read_xmp(data: &[u8]) -> Vec<(String, String)> {
+    let xmp = String::from_utf8_lossy(data);
+    KEYS.iter()
+        .filter_map(|key| {
+            let pattern = format!("screenshot:{}=\"", key);
+            let start = xmp.find(&pattern)? + pattern.len();
+            let end = start + xmp[start..].find('"')?;
+            Some((key.to_string(), xml_unescape(&xmp[start..end])))
+        })
+        .collect()
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&quot;", "\"")
+        .replace("&gt;", ">")
+        .replace("&lt;", "<")
+        .replace("&amp;", "&")
+}
+
+/// PNG 块使用的 CRC-32
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source() -> SourceInfo {
+        SourceInfo {
+            path: PathBuf::from("/视频/a & \"b\".mp4"),
+            size: 1234,
+            modified: 1_600_000_000,
+            duration: Some(61.5),
+            settings: "rows=5,cols=3".to_string(),
+        }
+    }
+
+    #[test]
+    fn png_crc() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let mut png = PNG_SIGNATURE.to_vec();
+        png.extend_from_slice(&13u32.to_be_bytes());
+        png.extend_from_slice(b"IHDR");
+        png.extend_from_slice(&[0; 17]);
+        png.extend_from_slice(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]);
+        let out = embed(png.clone(), "png", &source()).unwrap();
+        assert!(out.ends_with(&png[33..]));
+        assert_eq!(read(&out), Some(source()));
+    }
+
+    #[test]
+    fn jpeg_round_trip() {
+        let jpeg = vec![
+            0xFF, 0xD8, 0xFF, 0xE0, 0, 4, 0, 0, 0xFF, 0xDA, 0, 2, 0xFF, 0xD9,
+        ];
+        let out = embed(jpeg.clone(), "jpg", &source()).unwrap();
+        assert_eq!(&out[..8], &jpeg[..8]);
+        assert_eq!(&out[8..10], &[0xFF, 0xE1]);
+        assert!(out.ends_with(&jpeg[8..]));
+        assert_eq!(read(&out), Some(source()));
+    }
+
+    #[test]
+    fn webp_round_trip() {
+        // 100x50 的无损图像头
+        let bits: u32 = 99 | (49 << 14);
+        let mut vp8l = vec![0x2F];
+        vp8l.extend_from_slice(&bits.to_le_bytes());
+        let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+        push_riff_chunk(&mut webp, b"VP8L", &vp8l);
+        let out = embed(webp, "webp", &source()).unwrap();
+        let chunks: Vec<_> = webp_chunks(&out).collect();
+        assert_eq!(chunks[0].0, b"VP8X");
+        assert_eq!(chunks[0].1, [0x04, 0, 0, 0, 99, 0, 0, 49, 0, 0]);
+        assert_eq!(chunks[1].0, b"VP8L");
+        assert_eq!(
+            u32::from_le_bytes(out[4..8].try_into().unwrap()) as usize,
+            out.len() - 8
+        );
+        assert_eq!(read(&out), Some(source()));
+    }
+}
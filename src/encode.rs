@@ -0,0 +1,143 @@
+//! 把 BGR24 的帧编码成视频或动图
+
+use anyhow::{Context as _, Result};
+use ffmpeg_next as ffmpeg;
+use std::path::Path;
+
+use ffmpeg::{encoder, format, frame, Dictionary, Rational};
+
+use crate::filter::FilterGraph;
+
+/// 输出文件的容器和编码
+#[derive(Debug, Clone)]
+pub struct EncodeSettings<'a> {
+    /// 容器格式，如 `gif`、`webp`、`mp4`
+    pub muxer: &'a str,
+    /// 编码器，如 `gif`、`libwebp_anim`、`libx264`
+    pub codec: &'a str,
+    /// BGR24 转换到编码器输入格式的 filter graph
+    pub filter: &'a str,
+    /// 编码器的参数
+    pub codec_options: &'a [(&'a str, &'a str)],
+    pub width: u32,
+    pub height: u32,
+    /// 帧的 pts 的时间基
+    pub time_base: Rational,
+}
+
+pub struct VideoWriter {
+    octx: format::context::Output,
+    encoder: encoder::video::Encoder,
+    filter: FilterGraph,
+    width: u32,
+    height: u32,
+    time_base: Rational,
+    stream_time_base: Rational,
+
+    // buffer
+    filtered_frame: frame::Video,
+}
+
+impl VideoWriter {
+    pub fn create(path: &Path, settings: &EncodeSettings) -> Result<Self> {
+        let codec = encoder::find_by_name(settings.codec)
+            .with_context(|| format!("encoder {} not found", settings.codec))?;
+        let mut filter = FilterGraph::for_bgr(
+            settings.filter,
+            settings.width,
+            settings.height,
+            settings.time_base,
+        )?;
+        let (pixel_format, width, height, _) = filter.output();
+
+        let mut octx = format::output_as(&path, settings.muxer)
+            .with_context(|| format!("create output {} failed", path.display()))?;
+        let global_header = octx
+            .format()
+            .flags()
+            .contains(format::flag::Flags::GLOBAL_HEADER);
+        let mut ost = octx.add_stream(codec).context("add stream failed")?;
+        let mut encoder = ffmpeg::codec::context::Context::from_parameters(ost.parameters())?
+            .encoder()
+            .video()?;
+        encoder.set_width(width);
+        encoder.set_height(height);
+        encoder.set_format(pixel_format);
+        encoder.set_time_base(settings.time_base);
+        if global_header {
+            encoder.set_flags(ffmpeg::codec::flag::Flags::GLOBAL_HEADER);
+        }
+        let mut options = Dictionary::new();
+        for (key, value) in settings.codec_options {
+            options.set(key, value);
+        }
+        let encoder = encoder
+            .open_as_with(codec, options)
+            .with_context(|| format!("open encoder {} failed", settings.codec))?;
+        ost.set_parameters(&encoder);
+        ost.set_time_base(settings.time_base);
+        octx.write_header().context("write header failed")?;
+        let stream_time_base = octx.stream(0).context("no output stream")?.time_base();
+
+        Ok(Self {
+            octx,
+            encoder,
+            filter,
+            width: settings.width,
+            height: settings.height,
+            time_base: settings.time_base,
+            stream_time_base,
+            filtered_frame: frame::Video::empty(),
+        })
+    }
+
+    /// 写入一帧 BGR24 的图像，每行 `line_size` 字节
+    pub fn write(&mut self, data: &[u8], line_size: usize, pts: i64) -> Result<()> {
+        // filter graph 会拿走帧的数据，每次都要新建
+        let mut frame = frame::Video::new(format::Pixel::BGR24, self.width, self.height);
+        let width = self.width as usize;
+        let dst_line_size = frame.stride(0);
+        let dst = frame.data_mut(0);
+        for row in 0..self.height as usize {
+            dst[row * dst_line_size..row * dst_line_size + width * 3]
+                .copy_from_slice(&data[row * line_size..row * line_size + width * 3]);
+        }
+        frame.set_pts(Some(pts));
+        self.filter.push(&frame)?;
+        self.encode_filtered_frames()
+    }
+
+    /// 输入结束，写入剩下的帧和文件尾
+    pub fn finish(mut self) -> Result<()> {
+        self.filter.flush();
+        self.encode_filtered_frames()?;
+        self.encoder
+            .send_eof()
+            .context("send eof to encoder failed")?;
+        self.write_packets()?;
+        self.octx.write_trailer().context("write trailer failed")?;
+        Ok(())
+    }
+
+    fn encode_filtered_frames(&mut self) -> Result<()> {
+        while self.filter.pull(&mut self.filtered_frame) {
+            self.encoder
+                .send_frame(&self.filtered_frame)
+                .context("send frame to encoder failed")?;
+            self.write_packets()?;
+        }
+        Ok(())
+    }
+
+    fn write_packets(&mut self) -> Result<()> {
+        let mut packet = ffmpeg::Packet::empty();
+        while self.encoder.receive_packet(&mut packet).is_ok() {
+            packet.set_stream(0);
+            packet.rescale_ts(self.time_base, self.stream_time_base);
+            packet
+                .write_interleaved(&mut self.octx)
+                .context("write packet failed")?;
+        }
+        Ok(())
+    }
+}
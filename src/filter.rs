@@ -0,0 +1,147 @@
+//! 解码之后、缩放之前的 libavfilter 处理
+
+use anyhow::{Context as _, Result};
+use ffmpeg_next as ffmpeg;
+
+use ffmpeg::{decoder, filter, format::Pixel, frame, Rational};
+
+/// 用户指定的 filter graph，比如 `yadif,eq=brightness=0.05`
+pub struct FilterGraph {
+    spec: String,
+    source: SourceFormat,
+    graph: filter::Graph,
+}
+
+/// filter graph 输入的格式
+#[derive(Debug, Clone, Copy)]
+struct SourceFormat {
+    format: Pixel,
+    width: u32,
+    height: u32,
+    time_base: Rational,
+    aspect_ratio: Rational,
+}
+
+impl FilterGraph {
+    /// 输入为解码器输出的帧
+    pub fn new(spec: &str, decoder: &decoder::Video, time_base: Rational) -> Result<Self> {
+        let source = SourceFormat {
+            format: decoder.format(),
+            width: decoder.width(),
+            height: decoder.height(),
+            time_base,
+            aspect_ratio: decoder.aspect_ratio(),
+        };
+        Self::with_source(spec, source)
+    }
+
+    /// 输入为 BGR24 的帧
+    pub fn for_bgr(spec: &str, width: u32, height: u32, time_base: Rational) -> Result<Self> {
+        let source = SourceFormat {
+            format: Pixel::BGR24,
+            width,
+            height,
+            time_base,
+            aspect_ratio: Rational::new(1, 1),
+        };
+        Self::with_source(spec, source)
+    }
+
+    fn with_source(spec: &str, source: SourceFormat) -> Result<Self> {
+        let graph = Self::build(spec, source)?;
+        Ok(Self {
+            spec: spec.to_string(),
+            source,
+            graph,
+        })
+    }
+
+    fn build(spec: &str, source: SourceFormat) -> Result<filter::Graph> {
+        let mut graph = filter::Graph::new();
+        let aspect_ratio = match source.aspect_ratio {
+            r if r.numerator() > 0 && r.denominator() > 0 => r,
+            _ => Rational::new(1, 1),
+        };
+        let args = format!(
+            "video_size={}x{}:pix_fmt={}:time_base={}:pixel_aspect={}",
+            source.width,
+            source.height,
+            ffmpeg::sys::AVPixelFormat::from(source.format) as i32,
+            source.time_base,
+            aspect_ratio,
+        );
+        debug!("filter graph source: {}", args);
+        graph
+            .add(
+                &filter::find("buffer").context("no buffer filter")?,
+                "in",
+                &args,
+            )
+            .context("add buffer filter failed")?;
+        graph
+            .add(
+                &filter::find("buffersink").context("no buffersink filter")?,
+                "out",
+                "",
+            )
+            .context("add buffersink filter failed")?;
+        graph
+            .output("in", 0)?
+            .input("out", 0)?
+            .parse(spec)
+            .with_context(|| format!("parse filter graph `{}` failed", spec))?;
+        graph
+            .validate()
+            .with_context(|| format!("validate filter graph `{}` failed", spec))?;
+        trace!("filter graph:\n{}", graph.dump());
+        Ok(graph)
+    }
+
+    /// 输出的 (格式, 宽, 高, SAR)
+    pub fn output(&mut self) -> (Pixel, u32, u32, Rational) {
+        let sink = self.graph.get("out").expect("buffersink exists");
+        unsafe {
+            let ctx = sink.as_ptr();
+            let format: ffmpeg::sys::AVPixelFormat =
+                std::mem::transmute(ffmpeg::sys::av_buffersink_get_format(ctx));
+            (
+                Pixel::from(format),
+                ffmpeg::sys::av_buffersink_get_w(ctx) as u32,
+                ffmpeg::sys::av_buffersink_get_h(ctx) as u32,
+                Rational::from(ffmpeg::sys::av_buffersink_get_sample_aspect_ratio(ctx)),
+            )
+        }
+    }
+
+    /// 输出帧的时间基
+    pub fn time_base(&mut self) -> Rational {
+        let sink = self.graph.get("out").expect("buffersink exists");
+        unsafe { Rational::from(ffmpeg::sys::av_buffersink_get_time_base(sink.as_ptr())) }
+    }
+
+    /// seek 之后重新建立，丢掉 filter 里缓存的帧
+    pub fn reset(&mut self) -> Result<()> {
+        self.graph = Self::build(&self.spec, self.source)?;
+        Ok(())
+    }
+
+    pub fn push(&mut self, frame: &frame::Video) -> Result<()> {
+        let mut source = self.graph.get("in").expect("buffer exists");
+        source
+            .source()
+            .add(frame)
+            .context("send frame to filter graph failed")
+    }
+
+    /// 输入结束，之后可以取出 filter 里剩下的帧
+    pub fn flush(&mut self) {
+        let mut source = self.graph.get("in").expect("buffer exists");
+        source.source().flush().ok();
+    }
+
+    /// 取出一帧，没有可用的帧时返回 false
+    pub fn pull(&mut self, frame: &mut frame::Video) -> bool {
+        let mut sink = self.graph.get("out").expect("buffersink exists");
+        sink.sink().frame(frame).is_ok()
+    }
+}
@@ -1,9 +1,18 @@
-use crate::utils;
+use crate::{
+    filter::FilterGraph,
+    quality::{FrameQuality, QualityFilter},
+    scene::Histogram,
+    tonemap::{ToneMapper, ToneMapping, Transfer},
+    transform::{self, Rotation},
+    utils,
+};
 use anyhow::{bail, Context as _, Result};
 use ffmpeg_next as ffmpeg;
+use serde::Serialize;
+use std::collections::HashSet;
 use std::path::Path;
 
-use ffmpeg::{decoder, format, frame, software::scaling, Rational};
+use ffmpeg::{decoder, format, frame, software::scaling, Rational, Rescale};
 
 fn gcd(a: i64, b: i64) -> i64 {
     if b == 0 {
@@ -13,22 +22,166 @@ fn gcd(a: i64, b: i64) -> i64 {
     }
 }
 
+/// 每个位置如何选择截图
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FrameSelection {
+    /// 均匀分布的时间点
+    #[default]
+    Uniform,
+    /// 在每个位置附近解码 `candidates` 帧，选择和前后位置差异最大的一帧。
+    /// 前一个位置用已选的截图，后一个位置用它中点的帧
+    SceneChange { candidates: u32 },
+}
+
+/// 缩放算法
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScalingAlgorithm {
+    FastBilinear,
+    Bilinear,
+    Bicubic,
+    /// 缩小时效果最好
+    #[default]
+    Area,
+    Lanczos,
+    Spline,
+}
+
+impl ScalingAlgorithm {
+    fn flags(self) -> scaling::Flags {
+        let flags = match self {
+            Self::FastBilinear => scaling::Flags::FAST_BILINEAR,
+            Self::Bilinear => scaling::Flags::BILINEAR,
+            Self::Bicubic => scaling::Flags::BICUBIC,
+            Self::Area => scaling::Flags::AREA,
+            Self::Lanczos => scaling::Flags::LANCZOS,
+            Self::Spline => scaling::Flags::SPLINE,
+        };
+        flags | scaling::Flags::ACCURATE_RND | scaling::Flags::FULL_CHR_H_INT
+    }
+}
+
+impl std::str::FromStr for ScalingAlgorithm {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_lowercase().as_str() {
+            "fast-bilinear" | "fast_bilinear" => Self::FastBilinear,
+            "bilinear" => Self::Bilinear,
+            "bicubic" => Self::Bicubic,
+            "area" => Self::Area,
+            "lanczos" => Self::Lanczos,
+            "spline" => Self::Spline,
+            _ => bail!("unknown scaling algorithm {}", s),
+        })
+    }
+}
+
+/// 隔行扫描的视频如何处理
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Deinterlace {
+    Off,
+    /// 帧或视频流标记为隔行扫描时才处理
+    #[default]
+    Auto,
+    /// 所有帧都按隔行扫描处理
+    Force,
+}
+
+impl std::str::FromStr for Deinterlace {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_lowercase().as_str() {
+            "off" | "no" => Self::Off,
+            "auto" => Self::Auto,
+            "force" | "on" | "yes" => Self::Force,
+            _ => bail!("unknown deinterlace mode {}", s),
+        })
+    }
+}
+
+/// 截图的选项
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    pub selection: FrameSelection,
+    /// 跳过黑屏、纯色和模糊的截图，`None` 表示不检查
+    pub quality: Option<QualityFilter>,
+    /// 指定截图的时间点（秒），为空时在范围内均匀分布
+    pub timestamps: Vec<Rational>,
+    /// 截图范围的开始，默认为视频开头
+    pub start: Option<Rational>,
+    /// 截图范围的结束，默认为视频结尾。负数表示距离结尾的时间
+    pub end: Option<Rational>,
+    /// 从前一个关键帧开始解码，直到时间点，而不是直接使用之后的关键帧。更准确但更慢
+    pub accurate_seek: bool,
+    /// 解码器的线程数，0 表示由 ffmpeg 自动决定
+    pub decoder_threads: usize,
+    /// 同时截图的数量，每个 worker 打开一次输入文件，负责一段连续的位置
+    pub parallel: usize,
+    /// 缩放算法
+    pub scaling: ScalingAlgorithm,
+    /// HDR 视频的色调映射算法
+    pub tone_mapping: ToneMapping,
+    /// HDR 视频的峰值亮度 (nits)，默认为 1000
+    pub hdr_peak: Option<f32>,
+    /// 保留四周的黑边。默认按所有截图共同的黑边裁剪
+    pub keep_black_bars: bool,
+    /// 隔行扫描的视频只取一场再缩放，避免梳状条纹
+    pub deinterlace: Deinterlace,
+    /// 解码之后、缩放之前使用的 libavfilter filter graph，比如 `yadif,eq=brightness=0.05`
+    pub filter: Option<String>,
+    /// 不缩小，截图使用视频的显示尺寸
+    pub full_resolution: bool,
+}
+
+/// 用约分后的分子分母比较时间
+fn time_key(time: Rational) -> (i32, i32) {
+    let time = time.reduce();
+    (time.numerator(), time.denominator())
+}
+
 pub struct FrameExtractor {
     ictx: format::context::Input,
 
     time_base: Rational,
     duration_s: Rational,
+    /// 截图的范围 [start, end)
+    range: (Rational, Rational),
     input_stream_index: usize,
 
     decoder: decoder::Video,
+    /// 解码之后、缩放之前的 filter graph
+    filter: Option<FilterGraph>,
 
     scaler: scaling::Context,
+    /// 输入为一场（一半的行）的 scaler，用于去隔行
+    field_scaler: Option<scaling::Context>,
+    /// 视频流标记的场序
+    field_order: ffmpeg::FieldOrder,
+    /// HDR 视频先缩放成 BGR48，再映射到 BGR24
+    tone_mapper: Option<ToneMapper>,
+    /// 缩放之后再旋转，和播放器显示的方向一致
+    rotation: Rotation,
 
     packets_generated: u32,
     num_of_frames: u32,
+    /// 只截取 [packets_generated, end_slot) 的位置
+    end_slot: u32,
+    options: ExtractOptions,
+    last_histogram: Option<Histogram>,
+    /// 已经输出过的帧的时间，避免两张截图是同一帧
+    extracted_times: HashSet<(i32, i32)>,
 
     // buffer
+    /// 使用 filter graph 时，解码器的输出
+    raw_frame: frame::Video,
     decoded_frame: frame::Video,
+    skipped_frame: frame::Video,
+    hdr_frame: frame::Video,
+    rotated_frame: frame::Video,
     pub extracted_bgr_frame: frame::Video,
     pub extracted_bgr_frame_time: utils::VideoDuration,
 
@@ -36,7 +189,12 @@ pub struct FrameExtractor {
     pub info: crate::info::Info,
 }
 impl FrameExtractor {
-    pub fn new(input_file: &Path, num_of_frames: u32, scaled_frame_size: u32) -> Result<Self> {
+    pub fn new(
+        input_file: &Path,
+        num_of_frames: u32,
+        scaled_frame_size: u32,
+        mut options: ExtractOptions,
+    ) -> Result<Self> {
         let ictx = ffmpeg::format::input(&input_file).context("open input failed")?;
 
         let ist = ictx
@@ -55,9 +213,14 @@ impl FrameExtractor {
             ist.frames()
         );
 
-        let decoder = ffmpeg::codec::context::Context::from_parameters(ist.parameters())?
-            .decoder()
-            .video()?;
+        let mut decoder_context =
+            ffmpeg::codec::context::Context::from_parameters(ist.parameters())?;
+        decoder_context.set_threading(ffmpeg::codec::threading::Config {
+            kind: ffmpeg::codec::threading::Type::Frame,
+            count: options.decoder_threads,
+            ..Default::default()
+        });
+        let decoder = decoder_context.decoder().video()?;
         let video_codec = decoder.codec().context("no codec found")?;
         debug!(
             "video size: W {} x H {}, codec {}",
@@ -68,16 +231,110 @@ impl FrameExtractor {
 
         let duration_s = Self::decide_duration(&ictx, &ist)?;
         debug!("video duration: {}", utils::VideoDuration(duration_s));
+        let range = Self::decide_range(&options, duration_s)?;
+        // 指定的时间点也要在截图范围内
+        let requested = options.timestamps.len();
+        options.timestamps.retain(|t| {
+            let valid = *t >= range.0 && *t < range.1;
+            if !valid {
+                warn!(
+                    "timestamp {} out of range {} - {}, ignored",
+                    utils::VideoDuration(*t),
+                    utils::VideoDuration(range.0),
+                    utils::VideoDuration(range.1)
+                );
+            }
+            valid
+        });
+        // 不能退回均匀截图，排版的行数已经按时间点数量计算
+        if requested > 0 && options.timestamps.is_empty() {
+            bail!("指定的 {} 个时间点都不在截图范围内", requested);
+        }
+        let num_of_frames = match options.timestamps.len() {
+            0 => num_of_frames,
+            n => n as u32,
+        };
 
-        let scaler = scaling::Context::get(
-            decoder.format(),
-            decoder.width(),
-            decoder.height(),
-            ffmpeg::util::format::Pixel::BGR24,
-            scaled_frame_size,
-            decoder.height() * scaled_frame_size / decoder.width(),
-            scaling::Flags::BILINEAR,
+        let tone_mapper = match Transfer::detect(decoder.color_transfer_characteristic()) {
+            Some(transfer) if options.tone_mapping != ToneMapping::None => {
+                debug!(
+                    "HDR video ({:?}), tone mapping with {:?}",
+                    transfer, options.tone_mapping
+                );
+                Some(ToneMapper::new(
+                    transfer,
+                    options.tone_mapping,
+                    options.hdr_peak.unwrap_or(1000.0),
+                ))
+            }
+            _ => None,
+        };
+        let scaled_format = match tone_mapper {
+            Some(_) => ffmpeg::util::format::Pixel::BGR48LE,
+            None => ffmpeg::util::format::Pixel::BGR24,
+        };
+        let mut filter = match &options.filter {
+            Some(spec) => Some(FilterGraph::new(spec, &decoder, time_base)?),
+            None => None,
+        };
+        // 缩放的输入是 filter graph 的输出
+        let (source_format, source_width, source_height, source_aspect_ratio) = match &mut filter {
+            Some(filter) => filter.output(),
+            None => (
+                decoder.format(),
+                decoder.width(),
+                decoder.height(),
+                decoder.aspect_ratio(),
+            ),
+        };
+        let rotation = Self::decide_rotation(&ist);
+        let (display_width, display_height) =
+            Self::display_size(source_width, source_height, source_aspect_ratio, rotation);
+        debug!(
+            "display size: W {} x H {}, rotation {:?}",
+            display_width, display_height, rotation
+        );
+        let scaled_frame_size = if options.full_resolution {
+            display_width
+        } else {
+            scaled_frame_size
+        };
+        // 旋转之后宽度为 scaled_frame_size
+        let tile_height = ((display_height as u64 * scaled_frame_size as u64
+            / display_width.max(1) as u64) as u32)
+            .max(1);
+        let (scaled_width, scaled_height) = if rotation.is_transposed() {
+            (tile_height, scaled_frame_size)
+        } else {
+            (scaled_frame_size, tile_height)
+        };
+        let mut scaler = scaling::Context::get(
+            source_format,
+            source_width,
+            source_height,
+            scaled_format,
+            scaled_width,
+            scaled_height,
+            options.scaling.flags(),
         )?;
+        Self::set_scaler_colorspace(&mut scaler, &decoder);
+        let field_order = ffmpeg::FieldOrder::from(unsafe { (*decoder.as_ptr()).field_order });
+        debug!("field order: {:?}", field_order);
+        let field_scaler = if options.deinterlace != Deinterlace::Off && source_height >= 2 {
+            let mut field_scaler = scaling::Context::get(
+                source_format,
+                source_width,
+                source_height / 2,
+                scaled_format,
+                scaled_width,
+                scaled_height,
+                options.scaling.flags(),
+            )?;
+            Self::set_scaler_colorspace(&mut field_scaler, &decoder);
+            Some(field_scaler)
+        } else {
+            None
+        };
         #[cfg(not(feature = "info"))]
         let info = crate::info::Info;
 
@@ -99,18 +356,165 @@ impl FrameExtractor {
             ictx,
             time_base,
             duration_s,
+            range,
             input_stream_index,
             decoder,
+            filter,
             scaler,
+            field_scaler,
+            field_order,
+            tone_mapper,
+            rotation,
             packets_generated: 0,
             num_of_frames,
+            end_slot: num_of_frames,
+            options,
+            last_histogram: None,
+            extracted_times: HashSet::new(),
+            raw_frame: frame::Video::empty(),
             decoded_frame: frame::Video::empty(),
+            skipped_frame: frame::Video::empty(),
+            hdr_frame: frame::Video::empty(),
+            rotated_frame: frame::Video::empty(),
             extracted_bgr_frame: frame::Video::empty(),
             extracted_bgr_frame_time: utils::VideoDuration(Rational::new(0, 1)),
             info,
         })
     }
 
+    /// 只截取 `slots` 范围内的位置，用于多个 extractor 并行截图
+    pub fn with_slots(mut self, slots: std::ops::Range<u32>) -> Self {
+        self.packets_generated = slots.start.min(self.num_of_frames);
+        self.end_slot = slots.end.min(self.num_of_frames);
+        self
+    }
+
+    pub fn num_of_frames(&self) -> u32 {
+        self.num_of_frames
+    }
+
+    /// 截图的范围 [start, end)
+    pub fn range(&self) -> (Rational, Rational) {
+        self.range
+    }
+
+    /// 视频流的旋转，优先使用 display matrix，其次是 rotate 元数据
+    fn decide_rotation(ist: &format::stream::Stream) -> Rotation {
+        use ffmpeg::codec::packet::side_data::Type;
+
+        for side_data in ist.side_data() {
+            if side_data.kind() != Type::DisplayMatrix || side_data.data().len() < 9 * 4 {
+                continue;
+            }
+            // display matrix 给出的是逆时针的角度
+            let angle = unsafe {
+                ffmpeg::sys::av_display_rotation_get(side_data.data().as_ptr() as *const i32)
+            };
+            if angle.is_nan() {
+                continue;
+            }
+            return Rotation::from_degrees(-angle);
+        }
+        match ist.metadata().get("rotate").map(str::parse::<f64>) {
+            Some(Ok(degrees)) => Rotation::from_degrees(degrees),
+            _ => Rotation::None,
+        }
+    }
+
+    /// 按 SAR 和旋转修正后的显示尺寸
+    fn display_size(width: u32, height: u32, sar: Rational, rotation: Rotation) -> (u32, u32) {
+        let width = if sar.numerator() > 0 && sar.denominator() > 0 {
+            debug!("sample aspect ratio: {}", sar);
+            (width as u64 * sar.numerator() as u64 / sar.denominator() as u64) as u32
+        } else {
+            width
+        };
+        if rotation.is_transposed() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    /// 按视频流的色彩空间和范围转换到 BGR，否则 swscale 默认按 BT.601 有限范围处理
+    fn set_scaler_colorspace(scaler: &mut scaling::Context, decoder: &decoder::Video) {
+        use ffmpeg::sys::{self, AVColorSpace::*};
+        use ffmpeg::util::color;
+
+        let space = sys::AVColorSpace::from(decoder.color_space());
+        // SWS_CS_* 和 AVColorSpace 的取值是对应的
+        let src_colorspace = match space {
+            AVCOL_SPC_BT709 | AVCOL_SPC_FCC | AVCOL_SPC_BT470BG | AVCOL_SPC_SMPTE170M
+            | AVCOL_SPC_SMPTE240M | AVCOL_SPC_BT2020_NCL => space as i32,
+            AVCOL_SPC_BT2020_CL => sys::SWS_CS_BT2020 as i32,
+            // 没有标注时，按分辨率猜测
+            _ if decoder.height() >= 720 => sys::SWS_CS_ITU709 as i32,
+            _ => sys::SWS_CS_DEFAULT as i32,
+        };
+
+        let r = unsafe {
+            let ctx = scaler.as_mut_ptr();
+            let (mut inv_table, mut src_range, mut table, mut dst_range) =
+                (std::ptr::null_mut(), 0, std::ptr::null_mut(), 0);
+            let (mut brightness, mut contrast, mut saturation) = (0, 0, 0);
+            sys::sws_getColorspaceDetails(
+                ctx,
+                &mut inv_table,
+                &mut src_range,
+                &mut table,
+                &mut dst_range,
+                &mut brightness,
+                &mut contrast,
+                &mut saturation,
+            );
+            // yuvj 格式 swscale 已经当作全范围处理了
+            if decoder.color_range() == color::Range::JPEG {
+                src_range = 1;
+            }
+            debug!(
+                "scaler colorspace: {:?} -> {}, source range {}",
+                space, src_colorspace, src_range
+            );
+            sys::sws_setColorspaceDetails(
+                ctx,
+                sys::sws_getCoefficients(src_colorspace),
+                src_range,
+                table,
+                dst_range,
+                brightness,
+                contrast,
+                saturation,
+            )
+        };
+        if r < 0 {
+            warn!("set scaler colorspace failed: {}", r);
+        }
+    }
+
+    fn decide_range(options: &ExtractOptions, duration: Rational) -> Result<(Rational, Rational)> {
+        let zero = Rational::new(0, 1);
+        let start = options.start.unwrap_or(zero);
+        let end = match options.end {
+            Some(end) if end < zero => duration + end,
+            Some(end) if end < duration => end,
+            _ => duration,
+        };
+        if start < zero || start >= end {
+            bail!(
+                "invalid range {} - {} for video duration {}",
+                utils::VideoDuration(start),
+                utils::VideoDuration(end),
+                utils::VideoDuration(duration)
+            );
+        }
+        debug!(
+            "extracting frames in range {} - {}",
+            utils::VideoDuration(start),
+            utils::VideoDuration(end)
+        );
+        Ok((start, end))
+    }
+
     fn decide_duration(
         ictx: &format::context::Input,
         ist: &format::stream::Stream,
@@ -161,100 +565,491 @@ impl FrameExtractor {
     }
 
     pub fn extract_frame_to_internal_buffer(&mut self) -> Result<bool> {
-        'thumb_gen: while self.packets_generated < self.num_of_frames {
+        while self.packets_generated < self.end_slot {
             let i = self.packets_generated;
             self.packets_generated += 1;
-            let t =
-                self.duration_s * Rational::new((2 * i + 1) as i32, 2 * self.num_of_frames as i32);
-            debug!("seeking to {}", utils::VideoDuration(t));
-
-            // 这里的 position 是 AV_TIME_BASE，
-            // 参见文档 https://ffmpeg.org/doxygen/trunk/group__lavf__decoding.html#ga3b40fc8d2fda6992ae6ea2567d71ba30
-            let position = (f64::from(t) * ffmpeg::sys::AV_TIME_BASE as f64) as i64;
-            trace!(" seeking with position = {}", position);
-            self.ictx
-                .seek(position, position..)
-                .or_else(|e| {
+            let extracted = match self.options.selection {
+                FrameSelection::Uniform => self.extract_uniform_frame(i)?,
+                FrameSelection::SceneChange { candidates } => {
+                    self.extract_scene_frame(i, candidates.max(1))?
+                }
+            };
+            if !extracted {
+                continue;
+            }
+            // 两个位置截到同一帧时继续向后解码，保证截图数量不变
+            let mut time = self.extracted_bgr_frame_time.0;
+            while self.extracted_times.contains(&time_key(time)) {
+                debug!(
+                    "frame at {} already extracted, decoding forward",
+                    utils::VideoDuration(time)
+                );
+                if !self.extract_frame_after(time)? || self.extracted_bgr_frame_time.0 <= time {
                     warn!(
-                        "seek to {}.. failed: {e:#}, trying with more range",
-                        utils::VideoDuration(t)
-                    );
-                    self.ictx.seek(position, ..)
-                })
-                .with_context(|| format!("Seek to {} failed", utils::VideoDuration(t)))?;
-
-            for (stream, packet) in self.ictx.packets() {
-                if stream.index() == self.input_stream_index {
-                    debug!(
-                        "got one packet, sending to decoder... packet size: {}, position: {}, pts: {}",
-                        packet.size(),
-                        utils::VideoDuration(self.convert_pts(packet.position() as i64)),
-                        utils::VideoDuration(self.convert_pts(packet.pts().unwrap_or(0))),
+                        "no new frame after {}, keeping the duplicate",
+                        utils::VideoDuration(time)
                     );
-                    self.decoder
-                        .send_packet(&packet)
-                        .context("send packet to decoder failed")?;
-                    let frame_decoded = self
-                        .receive_and_process_decoded_frame()
-                        .context("receive and process decode frames error")?;
-                    if frame_decoded {
-                        return Ok(true);
-                    }
-                    continue 'thumb_gen;
+                    break;
                 }
+                time = self.extracted_bgr_frame_time.0;
             }
+            self.skip_time(time);
+            return Ok(true);
         }
-        self.decoder.send_eof().ok();
-        let frame_decoded = self
-            .receive_and_process_decoded_frame()
-            .context("receive and process decode frames after eof error")?;
-        Ok(frame_decoded)
+        Ok(false)
+    }
+
+    /// 把 `time` 当作已经截过的帧，再遇到时继续向后解码
+    pub fn skip_time(&mut self, time: Rational) {
+        self.extracted_times.insert(time_key(time));
+    }
+
+    /// 第 i 个位置的 (中点, 区间长度)。指定了时间点时区间长度为 0
+    fn slot(&self, i: u32) -> (Rational, Rational) {
+        if let Some(t) = self.options.timestamps.get(i as usize) {
+            return (*t, Rational::new(0, 1));
+        }
+        let (start, end) = self.range;
+        let len = (end - start) * Rational::new(1, self.num_of_frames as i32);
+        (start + len * Rational::new((2 * i + 1) as i32, 2), len)
     }
 
-    fn receive_and_process_decoded_frame(&mut self) -> Result<bool> {
-        if self.decoder.receive_frame(&mut self.decoded_frame).is_ok() {
-            let pts = self.convert_pts(self.decoded_frame.pts().unwrap_or(0));
-            let frame_time = pts;
+    /// 第 i 个位置的中点。截图不合格时，在区间内向两侧交替重试
+    fn extract_uniform_frame(&mut self, i: u32) -> Result<bool> {
+        let (t, len) = self.slot(i);
+        let filter = match self.options.quality.clone() {
+            Some(filter) if len > Rational::new(0, 1) => filter,
+            _ => return self.extract_frame_at(t),
+        };
+
+        // 第一张截图作为都不合格时的备选
+        let mut fallback: Option<(frame::Video, utils::VideoDuration)> = None;
+        let step = len * Rational::new(1, 2 * (filter.retries as i32 + 1));
+        for attempt in 0..=filter.retries as i32 {
+            // 0, +1, -1, +2, -2, ...
+            let k = (attempt + 1) / 2 * if attempt % 2 == 1 { 1 } else { -1 };
+            let t = t + step * Rational::new(k, 1);
+            if t < Rational::new(0, 1) || t >= self.duration_s {
+                continue;
+            }
+            if !self.extract_frame_at(t)? {
+                continue;
+            }
+            if self.check_quality(&filter) {
+                return Ok(true);
+            }
+            if fallback.is_none() {
+                let mut frame = frame::Video::empty();
+                std::mem::swap(&mut frame, &mut self.extracted_bgr_frame);
+                fallback = Some((frame, self.extracted_bgr_frame_time));
+            }
+        }
+        let Some((frame, time)) = fallback else { return Ok(false); };
+        warn!(
+            "no acceptable frame found near {}, using {}",
+            utils::VideoDuration(t),
+            time
+        );
+        self.extracted_bgr_frame = frame;
+        self.extracted_bgr_frame_time = time;
+        Ok(true)
+    }
+
+    fn check_quality(&self, filter: &QualityFilter) -> bool {
+        let quality = FrameQuality::from_frame(&self.extracted_bgr_frame);
+        let accepted = filter.accepts(&quality);
+        if !accepted {
             debug!(
-                " decoder got one frame: frame size W {} x H {}, format {:?}, kind {:?}, pts {}",
-                self.decoded_frame.width(),
-                self.decoded_frame.height(),
-                self.decoded_frame.format(),
-                self.decoded_frame.kind(),
-                utils::VideoDuration(frame_time),
+                "frame at {} rejected: {:?}",
+                self.extracted_bgr_frame_time, quality
             );
+        }
+        accepted
+    }
 
-            self.scaler
-                .run(&self.decoded_frame, &mut self.extracted_bgr_frame)
-                .context("Scale failed")?;
-            if self.extracted_bgr_frame.planes() != 1 {
-                bail!("scaled frame planes != 1");
+    /// 在第 i 个位置的区间内均匀解码若干帧，选出和上一张截图差异最大的。
+    /// 第一张截图没有参照，选择信息量最大的，避免选到黑屏。
+    /// 设置了质量过滤时优先选择合格的候选
+    fn extract_scene_frame(&mut self, i: u32, candidates: u32) -> Result<bool> {
+        let (center, len) = self.slot(i);
+        // 指定的时间点只有一个候选
+        let candidates = if len > Rational::new(0, 1) {
+            candidates
+        } else {
+            1
+        };
+        let slot_start = center - len * Rational::new(1, 2);
+        let filter = self.options.quality.clone();
+        let next_histogram = self.slot_histogram(i + 1)?;
+        let mut best: Option<((bool, f32), utils::VideoDuration, Histogram)> = None;
+        let mut best_frame = frame::Video::empty();
+        for c in 0..candidates {
+            let t = slot_start + len * Rational::new((2 * c + 1) as i32, 2 * candidates as i32);
+            if t < Rational::new(0, 1) || t >= self.duration_s {
+                continue;
+            }
+            if !self.extract_frame_at(t)? {
+                continue;
+            }
+            let histogram = Histogram::from_frame(&self.extracted_bgr_frame);
+            // 不合格的候选只在没有合格候选时使用
+            let accepted = match &filter {
+                Some(filter) => self.check_quality(filter),
+                None => true,
+            };
+            let score = match (&self.last_histogram, &next_histogram) {
+                (None, None) => histogram.entropy(),
+                (last, next) => [last, next]
+                    .into_iter()
+                    .flatten()
+                    .map(|h| histogram.distance(h))
+                    .sum(),
+            };
+            let score = (accepted, score);
+            trace!(
+                " candidate at {}, score {:?}",
+                self.extracted_bgr_frame_time,
+                score
+            );
+            let is_better = match &best {
+                Some((best_score, ..)) => score > *best_score,
+                None => true,
+            };
+            if is_better {
+                best = Some((score, self.extracted_bgr_frame_time, histogram));
+                std::mem::swap(&mut best_frame, &mut self.extracted_bgr_frame);
             }
-            self.extracted_bgr_frame_time = utils::VideoDuration(frame_time);
-            Ok(true)
+        }
+        let Some((score, time, histogram)) = best else { return Ok(false); };
+        debug!("selected frame at {} with score {:?}", time, score);
+        self.extracted_bgr_frame = best_frame;
+        self.extracted_bgr_frame_time = time;
+        self.last_histogram = Some(histogram);
+        Ok(true)
+    }
+
+    /// 第 i 个位置中点的帧的直方图，没有这个位置时为 `None`
+    fn slot_histogram(&mut self, i: u32) -> Result<Option<Histogram>> {
+        if i >= self.num_of_frames {
+            return Ok(None);
+        }
+        let (t, _) = self.slot(i);
+        if t >= self.duration_s || !self.extract_frame_at(t)? {
+            return Ok(None);
+        }
+        Ok(Some(Histogram::from_frame(&self.extracted_bgr_frame)))
+    }
+
+    /// seek 到 t 并解码出之后的第一帧。
+    /// 精确模式下 seek 到 t 之前的关键帧，然后一直解码到 t
+    fn extract_frame_at(&mut self, t: Rational) -> Result<bool> {
+        let accurate = self.options.accurate_seek;
+        self.decode_frame(t, accurate, |time| !accurate || time >= t)
+    }
+
+    /// 从 `t` 之前的关键帧开始解码，返回时间在 `t` 之后的第一帧
+    fn extract_frame_after(&mut self, t: Rational) -> Result<bool> {
+        self.decode_frame(t, true, |time| time > t)
+    }
+
+    /// seek 到 `t` 附近，返回第一个 `accept` 的帧。`backward` 时 seek 到 `t` 之前的关键帧
+    fn decode_frame(
+        &mut self,
+        t: Rational,
+        backward: bool,
+        accept: impl Fn(Rational) -> bool,
+    ) -> Result<bool> {
+        debug!("seeking to {}", utils::VideoDuration(t));
+
+        // 这里的 position 是 AV_TIME_BASE，
+        // 参见文档 https://ffmpeg.org/doxygen/trunk/group__lavf__decoding.html#ga3b40fc8d2fda6992ae6ea2567d71ba30
+        let position = (f64::from(t) * ffmpeg::sys::AV_TIME_BASE as f64) as i64;
+        trace!(" seeking with position = {}", position);
+        let seek_result = if backward {
+            self.ictx.seek(position, ..position)
         } else {
-            Ok(false)
+            self.ictx.seek(position, position..)
+        };
+        seek_result
+            .or_else(|e| {
+                warn!(
+                    "seek to {} failed: {e:#}, trying with more range",
+                    utils::VideoDuration(t)
+                );
+                self.ictx.seek(position, ..)
+            })
+            .with_context(|| format!("Seek to {} failed", utils::VideoDuration(t)))?;
+        // 丢掉 seek 之前留在解码器和 filter graph 里的帧
+        self.decoder.flush();
+        if let Some(filter) = &mut self.filter {
+            filter.reset()?;
+        }
+        let mut has_skipped = false;
+
+        loop {
+            let mut packet = ffmpeg::Packet::empty();
+            match packet.read(&mut self.ictx) {
+                Ok(()) => {}
+                Err(ffmpeg::Error::Eof) => break,
+                Err(e) => {
+                    trace!(" read packet failed: {e:#}");
+                    continue;
+                }
+            }
+            if packet.stream() != self.input_stream_index {
+                continue;
+            }
+            debug!(
+                "got one packet, sending to decoder... packet size: {}, position: {}, pts: {}",
+                packet.size(),
+                utils::VideoDuration(self.convert_pts(packet.position() as i64)),
+                utils::VideoDuration(self.convert_pts(packet.pts().unwrap_or(0))),
+            );
+            self.decoder
+                .send_packet(&packet)
+                .context("send packet to decoder failed")?;
+            while let Some(frame_time) = self.receive_decoded_frame() {
+                if accept(frame_time) {
+                    self.process_decoded_frame(frame_time)
+                        .context("process decoded frame error")?;
+                    return Ok(true);
+                }
+                trace!(" skipping frame at {}", utils::VideoDuration(frame_time));
+                std::mem::swap(&mut self.decoded_frame, &mut self.skipped_frame);
+                has_skipped = true;
+            }
+        }
+        self.decoder.send_eof().ok();
+        while let Some(frame_time) = self.receive_decoded_frame() {
+            if accept(frame_time) {
+                self.process_decoded_frame(frame_time)
+                    .context("process decoded frame after eof error")?;
+                return Ok(true);
+            }
+            std::mem::swap(&mut self.decoded_frame, &mut self.skipped_frame);
+            has_skipped = true;
+        }
+        // t 在最后一帧之后，使用最后一帧
+        if has_skipped {
+            std::mem::swap(&mut self.decoded_frame, &mut self.skipped_frame);
+            let frame_time = self.convert_pts(self.decoded_frame.pts().unwrap_or(0));
+            self.process_decoded_frame(frame_time)
+                .context("process last decoded frame error")?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// 从第 i 个位置开始连续解码 `length` 秒，每一帧缩放后和相对开始的时间一起交给 `f`
+    pub fn extract_clip(
+        &mut self,
+        i: u32,
+        length: Rational,
+        mut f: impl FnMut(&frame::Video, Rational) -> Result<()>,
+    ) -> Result<()> {
+        let (t, _) = self.slot(i);
+        if !self.extract_frame_at(t)? {
+            return Ok(());
+        }
+        let start = self.extracted_bgr_frame_time.0;
+        f(&self.extracted_bgr_frame, Rational::new(0, 1))?;
+        while let Some(frame_time) = self.decode_next_frame()? {
+            if frame_time >= start + length {
+                break;
+            }
+            if frame_time <= start {
+                continue;
+            }
+            self.process_decoded_frame(frame_time)
+                .context("process decoded frame error")?;
+            f(&self.extracted_bgr_frame, frame_time - start)?;
+        }
+        Ok(())
+    }
+
+    /// 继续解码下一帧，返回这一帧的时间。没有更多的帧时返回 `None`
+    fn decode_next_frame(&mut self) -> Result<Option<Rational>> {
+        loop {
+            if let Some(frame_time) = self.receive_decoded_frame() {
+                return Ok(Some(frame_time));
+            }
+            let mut packet = ffmpeg::Packet::empty();
+            match packet.read(&mut self.ictx) {
+                Ok(()) => {}
+                Err(ffmpeg::Error::Eof) => {
+                    self.decoder.send_eof().ok();
+                    return Ok(self.receive_decoded_frame());
+                }
+                Err(e) => {
+                    trace!(" read packet failed: {e:#}");
+                    continue;
+                }
+            }
+            if packet.stream() != self.input_stream_index {
+                continue;
+            }
+            self.decoder
+                .send_packet(&packet)
+                .context("send packet to decoder failed")?;
         }
     }
 
-    fn save_bgr_frame(frame: &frame::Video, filename: &Path) -> Result<()> {
-        use std::io::Write;
-        let mut f = std::fs::File::create(filename)?;
-        let (w, h) = (
-            frame.plane_width(0) as usize,
-            frame.plane_height(0) as usize,
+    /// 从解码器（和 filter graph）取出一帧，返回这一帧的时间
+    fn receive_decoded_frame(&mut self) -> Option<Rational> {
+        let pts = match &mut self.filter {
+            None => {
+                self.decoder.receive_frame(&mut self.decoded_frame).ok()?;
+                self.decoded_frame.pts().unwrap_or(0)
+            }
+            Some(filter) => {
+                // 有的 filter 需要多帧才有输出，比如 yadif
+                while !filter.pull(&mut self.decoded_frame) {
+                    match self.decoder.receive_frame(&mut self.raw_frame) {
+                        Ok(()) => {}
+                        Err(ffmpeg::Error::Eof) => {
+                            filter.flush();
+                            if !filter.pull(&mut self.decoded_frame) {
+                                return None;
+                            }
+                            break;
+                        }
+                        Err(_) => return None,
+                    }
+                    if let Err(e) = filter.push(&self.raw_frame) {
+                        warn!("{:#}", e);
+                        return None;
+                    }
+                }
+                self.decoded_frame
+                    .pts()
+                    .unwrap_or(0)
+                    .rescale(filter.time_base(), self.time_base)
+            }
+        };
+        let frame_time = self.convert_pts(pts);
+        debug!(
+            " decoder got one frame: frame size W {} x H {}, format {:?}, kind {:?}, pts {}",
+            self.decoded_frame.width(),
+            self.decoded_frame.height(),
+            self.decoded_frame.format(),
+            self.decoded_frame.kind(),
+            utils::VideoDuration(frame_time),
         );
-        write!(f, "P6\n{w} {h}\n{}\n", 255)?;
-        let data = frame.data(0);
-        let linesize = data.len() / h;
+        Some(frame_time)
+    }
+
+    /// 需要去隔行时，返回先显示的是否为底场
+    fn interlaced_field(&self) -> Option<bool> {
+        use ffmpeg::FieldOrder;
 
-        for i in 0..h {
-            let mut src = &data[i * linesize..i * linesize + w * 3];
-            std::io::copy(&mut src, &mut f)?;
+        // filter graph 可能已经去隔行了，只看帧的标记
+        let stream_interlaced = self.filter.is_none()
+            && !matches!(
+                self.field_order,
+                FieldOrder::Unknown | FieldOrder::Progressive
+            );
+        let interlaced = match self.options.deinterlace {
+            Deinterlace::Off => false,
+            Deinterlace::Auto => self.decoded_frame.is_interlaced() || stream_interlaced,
+            Deinterlace::Force => true,
+        };
+        if !interlaced {
+            return None;
         }
+        let bottom_first = if self.decoded_frame.is_interlaced() {
+            !self.decoded_frame.is_top_first()
+        } else {
+            matches!(self.field_order, FieldOrder::BB | FieldOrder::BT)
+        };
+        Some(bottom_first)
+    }
+
+    fn process_decoded_frame(&mut self, frame_time: Rational) -> Result<()> {
+        let field = match (self.interlaced_field(), &self.field_scaler) {
+            (Some(bottom), Some(_)) => {
+                trace!(
+                    " deinterlacing, using {} field",
+                    if bottom { "bottom" } else { "top" }
+                );
+                Some(field_of(&self.decoded_frame, bottom))
+            }
+            _ => None,
+        };
+        let (scaler, input) = match (&field, self.field_scaler.as_mut()) {
+            (Some(field), Some(field_scaler)) => (field_scaler, field),
+            _ => (&mut self.scaler, &self.decoded_frame),
+        };
+        let output = match self.tone_mapper {
+            Some(_) => &mut self.hdr_frame,
+            None => &mut self.extracted_bgr_frame,
+        };
+        // 旋转过的帧尺寸和 scaler 的输出不同，需要重新分配
+        if output.width() != scaler.output().width || output.height() != scaler.output().height {
+            *output = frame::Video::empty();
+        }
+        scaler.run(input, output).context("Scale failed")?;
+
+        if let Some(tone_mapper) = &self.tone_mapper {
+            let (width, height) = (self.hdr_frame.width(), self.hdr_frame.height());
+            let output = &mut self.extracted_bgr_frame;
+            if output.format() != ffmpeg::util::format::Pixel::BGR24
+                || output.width() != width
+                || output.height() != height
+            {
+                *output = frame::Video::new(ffmpeg::util::format::Pixel::BGR24, width, height);
+            }
+            let line_size = output.stride(0);
+            tone_mapper.convert(
+                width as usize,
+                height as usize,
+                self.hdr_frame.data(0),
+                self.hdr_frame.stride(0),
+                output.data_mut(0),
+                line_size,
+            );
+        }
+        if self.extracted_bgr_frame.planes() != 1 {
+            bail!("scaled frame planes != 1");
+        }
+        if self.rotation != Rotation::None {
+            self.rotate_extracted_frame();
+        }
+        self.extracted_bgr_frame_time = utils::VideoDuration(frame_time);
         Ok(())
     }
 
+    fn rotate_extracted_frame(&mut self) {
+        let src = &self.extracted_bgr_frame;
+        let (width, height) = (src.width(), src.height());
+        let (rotated_width, rotated_height) = if self.rotation.is_transposed() {
+            (height, width)
+        } else {
+            (width, height)
+        };
+        let dst = &mut self.rotated_frame;
+        if dst.format() != ffmpeg::util::format::Pixel::BGR24
+            || dst.width() != rotated_width
+            || dst.height() != rotated_height
+        {
+            *dst = frame::Video::new(
+                ffmpeg::util::format::Pixel::BGR24,
+                rotated_width,
+                rotated_height,
+            );
+        }
+        let line_size = dst.stride(0);
+        transform::rotate_bgr(
+            self.rotation,
+            width as usize,
+            height as usize,
+            src.data(0),
+            src.stride(0),
+            dst.data_mut(0),
+            line_size,
+        );
+        std::mem::swap(&mut self.extracted_bgr_frame, &mut self.rotated_frame);
+    }
+
     pub fn convert_pts(&self, pts: i64) -> Rational {
         Self::safe_mul(pts, self.time_base)
             .with_context(|| format!("compute {} * {} failed", pts, self.time_base))
@@ -265,16 +1060,6 @@ impl FrameExtractor {
             })
     }
 
-    #[allow(dead_code)]
-    pub fn extract_frames_to_ppm(&mut self) -> Result<()> {
-        for (idx, frame) in self.enumerate() {
-            let frame = frame?;
-            let filename = format!("frame-{}-BGR.ppm", idx);
-            Self::save_bgr_frame(&frame, Path::new(&filename)).context("save BGR frame failed")?;
-        }
-        Ok(())
-    }
-
     fn safe_mul(a: i64, r: Rational) -> Result<Rational> {
         let r = r.reduce();
         let mut a = a * r.0 as i64;
@@ -290,6 +1075,27 @@ impl FrameExtractor {
     }
 }
 
+/// 只包含一场的帧：每隔一行取一行，和 `frame` 共用数据，不能比 `frame` 活得更久
+fn field_of(frame: &frame::Video, bottom: bool) -> frame::Video {
+    let mut field = frame::Video::empty();
+    unsafe {
+        let src = &*frame.as_ptr();
+        let dst = &mut *field.as_mut_ptr();
+        dst.format = src.format;
+        dst.width = src.width;
+        dst.height = src.height / 2;
+        for i in 0..src.data.len() {
+            if src.data[i].is_null() {
+                break;
+            }
+            let offset = if bottom { src.linesize[i] as isize } else { 0 };
+            dst.data[i] = src.data[i].offset(offset);
+            dst.linesize[i] = src.linesize[i] * 2;
+        }
+    }
+    field
+}
+
 impl Iterator for FrameExtractor {
     type Item = Result<frame::Video>;
 
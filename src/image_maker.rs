@@ -1,10 +1,16 @@
-use crate::{cli::Args, info::Info};
-use anyhow::Result;
+use crate::{
+    info::Info,
+    layout::{RenderOptions, SheetLayout},
+    transform::Crop,
+    utils::VideoDuration,
+};
+use anyhow::{Context as _, Result};
 use opencv::{
     core::{self as cv_core, prelude::*, Rect, Vector},
     imgcodecs, imgproc,
     prelude::*,
 };
+use std::path::{Path, PathBuf};
 
 const TIME_FONT_SIZE: f32 = 32.0;
 const TIME_FONT_COLOR: (u8, u8, u8) = (0xFF, 0xFF, 0xFF);
@@ -37,32 +43,56 @@ pub fn open_frame_data(
     Ok(mat)
 }
 
-/// 返回
+/// 裁剪图像，并保持比例缩放到 `width` 宽
+pub fn crop_image(image: &Mat, crop: &Crop, width: i32) -> Result<Mat> {
+    let (x, y, w, h) = crop.rect(image.cols() as usize, image.rows() as usize);
+    let roi = Mat::roi(image, Rect::new(x as i32, y as i32, w as i32, h as i32))?;
+    resize_image(&roi, width)
+}
+
+/// 按比例缩放到宽度 `width`，宽度相同时只复制
+pub fn resize_image(image: &Mat, width: i32) -> Result<Mat> {
+    let (w, h) = (image.cols(), image.rows());
+    let mut output = Mat::default();
+    if w == width {
+        image.copy_to(&mut output)?;
+    } else {
+        let height = (h as i64 * width as i64 / w.max(1) as i64).max(1) as i32;
+        imgproc::resize(
+            image,
+            &mut output,
+            cv_core::Size::new(width, height),
+            0.,
+            0.,
+            imgproc::INTER_AREA,
+        )?;
+    }
+    Ok(output)
+}
+
+/// 把截图拼接到一张图上，返回未编码的图像
 pub fn merge_images(
-    images: Vec<(Mat, String)>,
+    images: Vec<(Mat, VideoDuration)>,
     info: Info,
-    args: &Args,
-) -> Result<cv_core::Vector<u8>> {
+    layout: &SheetLayout,
+    options: &RenderOptions,
+) -> Result<Mat> {
     if images.is_empty() {
         anyhow::bail!("没有截图生成");
     }
-    if images.len() != args.num_of_frames() as usize {
+    if images.len() != layout.num_of_frames() as usize {
         warn!(
             "截图数量 {} 与预期 {} 不匹配，可能有截图生成错误",
             images.len(),
-            args.num_of_frames()
+            layout.num_of_frames()
         );
     }
     let (im_w, im_h) = (images[0].0.cols() as u32, images[0].0.rows() as u32);
-    let (mut rows, mut cols) = (args.rows, args.cols);
-    if im_w < im_h && rows > cols {
-        debug!("自动调整行列数，使得图片不会太高");
-        std::mem::swap(&mut rows, &mut cols);
-    }
-    let info_height = crate::info::info_area_height(args);
+    let (rows, cols) = layout.grid(im_w, im_h);
+    let info_height = crate::info::info_area_height(layout);
 
-    let canvas_w = im_w * cols + args.space * (cols + 1);
-    let canvas_h = im_h * rows + args.space * (rows + 1) + info_height;
+    let canvas_w = im_w * cols + layout.space * (cols + 1);
+    let canvas_h = im_h * rows + layout.space * (rows + 1) + info_height;
 
     let mut canvas = Mat::new_rows_cols_with_default(
         canvas_h as i32,
@@ -71,50 +101,76 @@ pub fn merge_images(
         cv_core::Scalar::all(255.),
     )?;
 
-    crate::info::plot_info(&mut canvas, info, args)?;
-
-    'row: for r in 0..rows {
-        for c in 0..cols {
-            let idx = r as usize * cols as usize + c as usize;
-            let Some((image, text)) = images.get(idx) else { break 'row; };
-            // put image to canvas
-            let x = args.space + c * (args.space + im_w);
-            let y = args.space + r * (args.space + im_h) + info_height;
-            let pos = Rect::new(x as i32, y as i32, im_w as i32, im_h as i32);
-            let mut roi = Mat::roi(&canvas, pos)?;
-            image.copy_to(&mut roi)?;
-            // draw border, shift one pixel out
-            let border_color = cv_core::Scalar::new(0., 0., 0., 0.);
-            let border_pos = Rect::new(
-                (x - 1) as i32,
-                (y - 1) as i32,
-                (im_w + 2) as i32,
-                (im_h + 2) as i32,
-            );
-            imgproc::rectangle(&mut canvas, border_pos, border_color, 1, imgproc::LINE_8, 0)?;
-
-            // draw text
-            #[cfg(not(feature = "font"))]
-            crate::text::draw_text(&mut canvas, text, x + 5, y + im_h - 5)?;
-            #[cfg(feature = "font")]
-            crate::text::draw_text(
-                &mut canvas,
-                text,
-                x + 5,
-                y + 5,
-                TIME_FONT_SIZE,
-                TIME_FONT_COLOR,
-                TIME_FONT_BG_COLOR,
-                args.font.as_deref(),
-            )?;
-        }
+    crate::info::plot_info(&mut canvas, info, layout, options)?;
+
+    let rects = layout.tile_rects(im_w, im_h, images.len(), info_height);
+    for ((image, time), (x, y, _, _)) in images.iter().zip(rects) {
+        let text = &time.to_string();
+        // put image to canvas
+        let pos = Rect::new(x as i32, y as i32, im_w as i32, im_h as i32);
+        let mut roi = Mat::roi(&canvas, pos)?;
+        image.copy_to(&mut roi)?;
+        // draw border, shift one pixel out
+        let border_color = cv_core::Scalar::new(0., 0., 0., 0.);
+        let border_pos = Rect::new(
+            (x - 1) as i32,
+            (y - 1) as i32,
+            (im_w + 2) as i32,
+            (im_h + 2) as i32,
+        );
+        imgproc::rectangle(&mut canvas, border_pos, border_color, 1, imgproc::LINE_8, 0)?;
+
+        // draw text
+        #[cfg(not(feature = "font"))]
+        crate::text::draw_text(&mut canvas, text, x + 5, y + im_h - 5)?;
+        #[cfg(feature = "font")]
+        crate::text::draw_text(
+            &mut canvas,
+            text,
+            x + 5,
+            y + 5,
+            TIME_FONT_SIZE,
+            TIME_FONT_COLOR,
+            TIME_FONT_BG_COLOR,
+            options.font.as_deref(),
+        )?;
+    }
+
+    Ok(canvas)
+}
+
+/// 把每一帧单独编码保存到 `dir`，文件名为 `<prefix>-<时间>.<ext>`
+pub fn save_frames(
+    frames: &[(Mat, VideoDuration)],
+    dir: &Path,
+    prefix: &str,
+    ext: &str,
+) -> Result<Vec<PathBuf>> {
+    std::fs::create_dir_all(dir).with_context(|| format!("create dir {} failed", dir.display()))?;
+    let mut paths = Vec::with_capacity(frames.len());
+    for (image, time) in frames {
+        // 文件名里不能有冒号
+        let path = dir.join(format!(
+            "{}-{}.{}",
+            prefix,
+            time.to_string().replace(':', "-"),
+            ext
+        ));
+        let buf = encode_image(image, ext)?;
+        std::fs::write(&path, buf.as_slice())
+            .with_context(|| format!("write {} failed", path.display()))?;
+        debug!("frame saved to {}", path.display());
+        paths.push(path);
     }
+    Ok(paths)
+}
 
-    // encode image
+/// 按扩展名编码图像
+pub fn encode_image(canvas: &Mat, ext: &str) -> Result<Vector<u8>> {
     let mut buf = Vector::new();
     let flags = Vector::new();
-    let ext = format!(".{}", args.ext);
-    imgcodecs::imencode(&ext, &canvas, &mut buf, &flags)?;
+    let ext = format!(".{}", ext);
+    imgcodecs::imencode(&ext, canvas, &mut buf, &flags)?;
 
     Ok(buf)
 }
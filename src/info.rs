@@ -1,4 +1,4 @@
-use crate::cli::Args;
+use crate::layout::{RenderOptions, SheetLayout};
 use anyhow::Result;
 use opencv::core::Mat;
 
@@ -27,17 +27,22 @@ pub struct Info {
 }
 
 #[cfg(not(feature = "info"))]
-pub fn info_area_height(_: &Args) -> u32 {
+pub fn info_area_height(_: &SheetLayout) -> u32 {
     0
 }
 
 #[cfg(feature = "info")]
-pub fn info_area_height(args: &Args) -> u32 {
-    args.space + LINE_HEIGHT * 3
+pub fn info_area_height(layout: &SheetLayout) -> u32 {
+    layout.space + LINE_HEIGHT * 3
 }
 
 #[cfg(not(feature = "info"))]
-pub fn plot_info(_image: &mut Mat, _info: Info, _args: &Args) -> Result<()> {
+pub fn plot_info(
+    _image: &mut Mat,
+    _info: Info,
+    _layout: &SheetLayout,
+    _options: &RenderOptions,
+) -> Result<()> {
     Ok(())
 }
 
@@ -59,10 +64,15 @@ pub fn readable_size(s: usize) -> String {
 }
 
 #[cfg(feature = "info")]
-pub fn plot_info(image: &mut Mat, info: Info, args: &Args) -> Result<()> {
+pub fn plot_info(
+    image: &mut Mat,
+    info: Info,
+    layout: &SheetLayout,
+    options: &RenderOptions,
+) -> Result<()> {
     use crate::text::draw_text;
 
-    let indent = args.space;
+    let indent = layout.space;
     draw_text(
         image,
         &format!(
@@ -75,7 +85,7 @@ pub fn plot_info(image: &mut Mat, info: Info, args: &Args) -> Result<()> {
         TEXT_SIZE,
         COLOR,
         BG_COLOR,
-        args.font.as_deref(),
+        options.font.as_deref(),
     )?;
     draw_text(
         image,
@@ -90,7 +100,7 @@ pub fn plot_info(image: &mut Mat, info: Info, args: &Args) -> Result<()> {
         TEXT_SIZE,
         COLOR,
         BG_COLOR,
-        args.font.as_deref(),
+        options.font.as_deref(),
     )?;
     draw_text(
         image,
@@ -100,7 +110,7 @@ pub fn plot_info(image: &mut Mat, info: Info, args: &Args) -> Result<()> {
         TEXT_SIZE,
         COLOR,
         BG_COLOR,
-        args.font.as_deref(),
+        options.font.as_deref(),
     )?;
 
     Ok(())
@@ -0,0 +1,120 @@
+/// 截图的排版
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SheetLayout {
+    /// 纵向数量
+    pub rows: u32,
+    /// 横向数量
+    pub cols: u32,
+    /// 横向尺寸
+    pub width: u32,
+    /// 图片之间的间隔
+    pub space: u32,
+    /// 竖屏视频是否自动交换行列数
+    pub auto_flip: bool,
+}
+
+impl Default for SheetLayout {
+    fn default() -> Self {
+        Self {
+            rows: 5,
+            cols: 3,
+            width: 2048,
+            space: 10,
+            auto_flip: true,
+        }
+    }
+}
+
+impl SheetLayout {
+    pub fn num_of_frames(&self) -> u32 {
+        self.rows * self.cols
+    }
+
+    pub fn scaled_frame_width(&self) -> u32 {
+        (self.width - (self.cols + 1) * self.space) / self.cols
+    }
+
+    /// 根据截图的尺寸（已经按旋转和 SAR 修正）决定实际的 (行, 列) 数
+    pub fn grid(&self, frame_width: u32, frame_height: u32) -> (u32, u32) {
+        if self.auto_flip && frame_width < frame_height && self.rows > self.cols {
+            debug!("自动调整行列数，使得图片不会太高");
+            (self.cols, self.rows)
+        } else {
+            (self.rows, self.cols)
+        }
+    }
+
+    /// 拼接后前 `count` 张截图的位置 (x, y, 宽, 高)，`top` 是上方信息区域的高度
+    pub fn tile_rects(
+        &self,
+        frame_width: u32,
+        frame_height: u32,
+        count: usize,
+        top: u32,
+    ) -> Vec<(u32, u32, u32, u32)> {
+        let (rows, cols) = self.grid(frame_width, frame_height);
+        (0..count.min((rows * cols) as usize) as u32)
+            .map(|i| {
+                let (r, c) = (i / cols, i % cols);
+                let x = self.space + c * (self.space + frame_width);
+                let y = self.space + r * (self.space + frame_height) + top;
+                (x, y, frame_width, frame_height)
+            })
+            .collect()
+    }
+}
+
+/// 截图的绘制和编码选项
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// 编码格式，如 `jpg`、`png`、`webp`
+    pub ext: String,
+    /// 使用字体的路径，不指定时使用内置字体
+    #[cfg(feature = "font")]
+    pub font: Option<std::path::PathBuf>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            ext: "jpg".to_string(),
+            #[cfg(feature = "font")]
+            font: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scaled_frame_width() {
+        let layout = SheetLayout::default();
+        assert_eq!(layout.num_of_frames(), 15);
+        assert_eq!(layout.scaled_frame_width(), (2048 - 4 * 10) / 3);
+    }
+
+    #[test]
+    fn tile_rects() {
+        let layout = SheetLayout::default();
+        let rects = layout.tile_rects(100, 50, 4, 30);
+        assert_eq!(rects.len(), 4);
+        assert_eq!(rects[0], (10, 40, 100, 50));
+        assert_eq!(rects[3], (10, 100, 100, 50));
+        assert_eq!(layout.tile_rects(100, 50, 20, 0).len(), 15);
+    }
+
+    #[test]
+    fn grid_auto_flip() {
+        let layout = SheetLayout::default();
+        assert_eq!(layout.grid(1920, 1080), (5, 3));
+        assert_eq!(layout.grid(1080, 1920), (3, 5));
+
+        let layout = SheetLayout {
+            auto_flip: false,
+            ..Default::default()
+        };
+        assert_eq!(layout.grid(1080, 1920), (5, 3));
+    }
+}
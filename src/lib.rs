@@ -0,0 +1,98 @@
+//! 生成视频截图（contact sheet）
+//!
+//! ```no_run
+//! # fn main() -> anyhow::Result<()> {
+//! screenshot::init()?;
+//! let jpg = screenshot::ContactSheet::new()
+//!     .rows(4)
+//!     .cols(4)
+//!     .render_path("video.mp4")?;
+//! std::fs::write("video.mp4.jpg", jpg)?;
+//! # Ok(())
+//! # }
+//! ```
+
+#[macro_use]
+extern crate tracing;
+
+use anyhow::{Context, Result};
+use ffmpeg_next as ffmpeg;
+
+pub mod animation;
+pub mod embed;
+mod encode;
+mod filter;
+mod frame_extractor;
+mod image_maker;
+pub mod info;
+pub mod layout;
+pub mod quality;
+mod scene;
+mod sheet;
+pub mod sidecar;
+pub mod sprite;
+mod teaser;
+mod text;
+mod tonemap;
+mod transform;
+mod utils;
+
+pub use animation::{AnimationFormat, AnimationOptions};
+pub use ffmpeg_next::Rational;
+pub use frame_extractor::{Deinterlace, ExtractOptions, FrameSelection, ScalingAlgorithm};
+pub use image_maker::{encode_image, save_frames};
+pub use info::Info;
+pub use layout::{RenderOptions, SheetLayout};
+pub use opencv::core::Mat;
+pub use quality::QualityFilter;
+pub use sheet::ContactSheet;
+pub use sidecar::Sidecar;
+pub use sprite::{Sprite, SpriteOptions};
+pub use teaser::{TeaserFormat, TeaserOptions};
+pub use tonemap::ToneMapping;
+pub use utils::{parse_duration, VideoDuration};
+
+/// 初始化 ffmpeg，可以重复调用
+pub fn init() -> Result<()> {
+    ffmpeg::init().context("ffmpeg init failed")
+}
+
+/// 用 ffmpeg 打开文件，判断是否是有时长的视频
+pub fn probe_video(path: impl AsRef<std::path::Path>) -> bool {
+    let Ok(input) = ffmpeg::format::input(&path.as_ref()) else { return false; };
+    let Some(stream) = input.streams().best(ffmpeg::media::Type::Video) else { return false; };
+    input.duration() > 0 && is_video_stream(input.format().name(), stream.parameters().id())
+}
+
+/// 图片会被 image2 和 `*_pipe` 识别为视频流，文本文件会被 tty 等格式识别为 ANSI 动画
+const NON_VIDEO_FORMATS: &[&str] = &["image2", "tty", "bin", "xbin", "idf"];
+
+fn is_video_stream(format: &str, codec: ffmpeg::codec::Id) -> bool {
+    use ffmpeg::codec::Id;
+    !NON_VIDEO_FORMATS.contains(&format)
+        && !format.ends_with("_pipe")
+        && !matches!(
+            codec,
+            Id::None | Id::ANSI | Id::BINTEXT | Id::XBIN | Id::IDF
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ffmpeg::codec::Id;
+
+    #[test]
+    fn probe_non_video() {
+        assert!(is_video_stream("matroska,webm", Id::H264));
+        assert!(!is_video_stream("png_pipe", Id::PNG));
+        assert!(!is_video_stream("tty", Id::ANSI));
+
+        init().unwrap();
+        let path =
+            std::env::temp_dir().join(format!("screenshot-probe-{}.nfo", std::process::id()));
+        std::fs::write(&path, "Title: video\n".repeat(100)).unwrap();
+        assert!(!probe_video(&path));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -3,20 +3,18 @@
 #[macro_use]
 extern crate tracing;
 
-use anyhow::{Context, Result};
+use anyhow::Result;
 use clap::Parser;
-use ffmpeg_next as ffmpeg;
 
 mod cli;
-mod frame_extractor;
-mod image_maker;
-mod info;
+mod config;
+mod manifest;
+mod output;
 mod process;
-mod text;
-mod utils;
+mod walk;
 
 fn _main() -> Result<()> {
-    ffmpeg::init().context("ffmpeg init failed")?;
+    screenshot::init()?;
     let args = cli::Args::parse();
     process::start(args)
 }
@@ -0,0 +1,169 @@
+//! 记录每个视频处理结果的清单，再次处理时跳过没有变化的视频
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// 清单中的一行
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub size: u64,
+    /// 视频的修改时间 (Unix 时间戳，秒)
+    pub modified: i64,
+    /// 处理参数的哈希
+    pub settings: String,
+    /// 失败的原因，成功时为空
+    pub error: Option<String>,
+    /// `--probe` 时判断不是视频，没有处理
+    #[serde(default)]
+    pub not_video: bool,
+}
+
+impl Entry {
+    /// 读取视频当前的大小和修改时间
+    pub fn new(path: &Path, settings: String) -> Result<Self> {
+        let meta = std::fs::metadata(path).context("read source metadata failed")?;
+        Ok(Self {
+            path: std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+            size: meta.len(),
+            modified: filetime::FileTime::from_last_modification_time(&meta).unix_seconds(),
+            settings,
+            error: None,
+            not_video: false,
+        })
+    }
+
+    fn same_input(&self, other: &Entry) -> bool {
+        (self.size, self.modified, &self.settings) == (other.size, other.modified, &other.settings)
+    }
+}
+
+/// JSON lines 格式的清单，每处理完一个视频追加一行，同一个路径以最后一行为准
+pub struct Manifest {
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+    file: Mutex<File>,
+}
+
+impl Manifest {
+    /// 读取已有的清单，打开时会去掉重复的行。无法解析的行原样保留
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut entries = HashMap::new();
+        let mut invalid = vec![];
+        let mut lines = 0;
+        if path.exists() {
+            let f = File::open(path)
+                .with_context(|| format!("open manifest {} failed", path.display()))?;
+            for line in BufReader::new(f).lines() {
+                let line = line.context("read manifest failed")?;
+                lines += 1;
+                match serde_json::from_str::<Entry>(&line) {
+                    Ok(entry) => {
+                        entries.insert(entry.path.clone(), entry);
+                    }
+                    Err(e) => {
+                        warn!("清单 {} 中有无法解析的行: {}", path.display(), e);
+                        invalid.push(line);
+                    }
+                }
+            }
+        }
+        if lines > entries.len() + invalid.len() {
+            debug!("compacting manifest {}", path.display());
+            let mut buf = String::new();
+            for line in &invalid {
+                buf.push_str(line);
+                buf.push('\n');
+            }
+            for entry in entries.values() {
+                buf.push_str(&serde_json::to_string(entry)?);
+                buf.push('\n');
+            }
+            // 先写入临时文件再替换，中断时不会丢失清单
+            let tmp = path.with_extension("tmp");
+            std::fs::write(&tmp, buf)
+                .with_context(|| format!("write manifest {} failed", tmp.display()))?;
+            std::fs::rename(&tmp, path)
+                .with_context(|| format!("replace manifest {} failed", path.display()))?;
+        }
+        let file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("open manifest {} failed", path.display()))?;
+        Ok(Self {
+            entries: Mutex::new(entries),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// 上次处理的结果，视频或参数有变化时为 `None`
+    pub fn previous(&self, entry: &Entry) -> Option<Entry> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(&entry.path)
+            .filter(|previous| previous.same_input(entry))
+            .cloned()
+    }
+
+    /// 记录处理结果并立即写入文件
+    pub fn record(&self, entry: Entry) -> Result<()> {
+        let line = serde_json::to_string(&entry)? + "\n";
+        {
+            let mut file = self.file.lock().unwrap();
+            file.write_all(line.as_bytes())
+                .context("write manifest failed")?;
+        }
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(entry.path.clone(), entry);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reopen_manifest() {
+        let dir = std::env::temp_dir().join(format!("screenshot-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("manifest.jsonl");
+        let video = dir.join("a.mp4");
+        std::fs::write(&video, b"video").unwrap();
+
+        let entry = Entry::new(&video, "abc".to_string()).unwrap();
+        let manifest = Manifest::open(&path).unwrap();
+        assert_eq!(manifest.previous(&entry), None);
+        manifest
+            .record(Entry {
+                error: Some("failed".to_string()),
+                ..entry.clone()
+            })
+            .unwrap();
+        manifest.record(entry.clone()).unwrap();
+        drop(manifest);
+        let mut file = File::options().append(true).open(&path).unwrap();
+        file.write_all(b"not json\n").unwrap();
+        drop(file);
+
+        let manifest = Manifest::open(&path).unwrap();
+        assert_eq!(manifest.previous(&entry), Some(entry.clone()));
+        let changed = Entry {
+            settings: "def".to_string(),
+            ..entry
+        };
+        assert_eq!(manifest.previous(&changed), None);
+        // 重复的行已经去掉，无法解析的行保留
+        let content = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(content.lines().count(), 2);
+        assert!(content.starts_with("not json\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
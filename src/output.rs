@@ -0,0 +1,83 @@
+//! 输出文件名模板
+
+use anyhow::{bail, Context, Result};
+use screenshot::SheetLayout;
+use std::path::Path;
+
+/// 默认在视频文件名后加上扩展名
+pub const DEFAULT_TEMPLATE: &str = "{name}.{ext}";
+/// `--remove-ext` 时替换视频的扩展名
+pub const REMOVE_EXT_TEMPLATE: &str = "{stem}.{ext}";
+
+/// 替换模板中的变量：
+///
+/// - `{name}`：视频文件名
+/// - `{stem}`：去掉扩展名的视频文件名
+/// - `{ext}`：输出文件的扩展名
+/// - `{rows}`、`{cols}`、`{width}`：截图的排版
+/// - `{hash}`：视频绝对路径的哈希，用于不按文件夹区分的缓存
+///
+/// 模板必须包含 `{ext}`，否则截图和其他输出的文件名会相同
+pub fn render(template: &str, input: &Path, layout: &SheetLayout, ext: &str) -> Result<String> {
+    if !template.contains("{ext}") {
+        bail!("文件名模板 {} 缺少 {{ext}}", template);
+    }
+    let name = input.file_name().context("input filename missing")?;
+    let stem = input.file_stem().context("input filename missing")?;
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let Some(len) = rest[start..].find('}') else {
+            bail!("文件名模板 {} 缺少 }}", template)
+        };
+        let key = &rest[start + 1..start + len];
+        match key {
+            "name" => out.push_str(&name.to_string_lossy()),
+            "stem" => out.push_str(&stem.to_string_lossy()),
+            "ext" => out.push_str(ext),
+            "rows" => out.push_str(&layout.rows.to_string()),
+            "cols" => out.push_str(&layout.cols.to_string()),
+            "width" => out.push_str(&layout.width.to_string()),
+            "hash" => out.push_str(&path_hash(input)),
+            _ => bail!("文件名模板中有未知的变量 {{{}}}", key),
+        }
+        rest = &rest[start + len + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn path_hash(path: &Path) -> String {
+    let path = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    hash(&path.to_string_lossy())
+}
+
+/// FNV-1a 哈希。标准库的哈希在不同版本间可能变化，不能用于缓存
+pub fn hash(s: &str) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for b in s.bytes() {
+        hash ^= b as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_template() {
+        let input = Path::new("/videos/a.b.mp4");
+        let layout = SheetLayout::default();
+        let render = |t| render(t, input, &layout, "jpg").unwrap();
+        assert_eq!(render(DEFAULT_TEMPLATE), "a.b.mp4.jpg");
+        assert_eq!(render(REMOVE_EXT_TEMPLATE), "a.b.jpg");
+        assert_eq!(render("{stem}_{rows}x{cols}.{ext}"), "a.b_5x3.jpg");
+        assert_eq!(render("{hash}.{ext}").len(), 20);
+        assert!(super::render("{size}.{ext}", input, &layout, "jpg").is_err());
+        assert!(super::render("{stem.{ext}", input, &layout, "jpg").is_err());
+        assert!(super::render("{stem}.jpg", input, &layout, "jpg").is_err());
+    }
+}
@@ -1,78 +1,194 @@
 use anyhow::{bail, Context, Result};
+use filetime::FileTime;
+use rayon::prelude::*;
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Instant;
 
-use crate::{cli, frame_extractor, image_maker};
+use screenshot::{
+    embed::{self, SourceInfo},
+    ContactSheet, Mat, SpriteOptions, VideoDuration,
+};
+
+use crate::{
+    cli,
+    config::Config,
+    manifest::{Entry, Manifest},
+    output, walk,
+};
 
 pub fn start(args: cli::Args) -> Result<()> {
     if !args.input.exists() {
         bail!("input file does not exist: {}", args.input.display());
     }
+    let config = Config::user(args.config.as_deref()).context("读取用户配置失败")?;
+    let manifest = match &args.manifest {
+        Some(path) => Some(Manifest::open(path).context("读取清单失败")?),
+        None => None,
+    };
     if args.input.is_dir() {
-        let (error_tx, error_rx) = mpsc::channel();
-        let args = Arc::new(args);
-        visit_recursive_dir(args.input.clone(), args.clone(), error_tx);
-        let mut errors = vec![];
-        while let Ok((path, e)) = error_rx.recv() {
-            if args.ignore_error {
-                bail!("处理文件 {} 错误: {:#}", path.display(), e);
-            }
-            errors.push((path, e));
-        }
-
-        info!("处理文件完成, 有 {} 个文件处理失败：", errors.len());
-        for (path, e) in errors.iter() {
-            error!("处理文件 {} 错误: {:#}", path.display(), e);
-        }
+        process_dir(&args, &config, manifest.as_ref())?;
     } else {
-        run(&args.input, &args)
+        let config = match args.input.parent() {
+            Some(dir) => config.for_dir(dir)?,
+            None => config,
+        };
+        run(&args.input, &args, &config, manifest.as_ref(), false)
             .with_context(|| format!("处理文件 {} 错误", args.input.display()))?;
     }
     Ok(())
 }
 
-fn run(file: &std::path::Path, args: &cli::Args) -> Result<()> {
-    assert!(file.exists());
-    assert!(file.is_file());
+fn run(
+    file: &std::path::Path,
+    args: &cli::Args,
+    config: &Config,
+    manifest: Option<&Manifest>,
+    probe: bool,
+) -> Result<()> {
+    // 遍历之后文件可能已经被删除
+    if !file.is_file() {
+        bail!("not a file: {}", file.display());
+    }
 
-    let output = args.output_name(file)?;
+    let config = config.clone().merge(args.overrides());
+    let sheet = config.sheet().extract_options(args.extract_options());
+    let output = args.output_name(file, &sheet.effective_layout(), &sheet.options.ext)?;
+    // --no-save 时没有保存任何文件，不使用清单
+    let manifest = match manifest.filter(|_| !args.no_save) {
+        Some(manifest) => {
+            let entry = Entry::new(file, output::hash(&settings_key(&sheet, args, &output)))?;
+            match manifest.previous(&entry) {
+                Some(Entry {
+                    not_video: true, ..
+                }) => {
+                    debug!("清单中记录 {} 不是视频, 跳过", file.display());
+                    return Ok(());
+                }
+                // 截图被删除时重新生成
+                Some(Entry { error: None, .. }) if args.no_sheet || output.exists() => {
+                    info!("清单中记录已处理过 {}, 跳过", file.display());
+                    return Ok(());
+                }
+                Some(Entry { error: Some(e), .. }) if !args.retry_failed => {
+                    info!("清单中记录上次处理 {} 失败: {}, 跳过", file.display(), e);
+                    return Ok(());
+                }
+                _ => {}
+            }
+            Some((manifest, entry))
+        }
+        None => None,
+    };
+    // 先检查清单，跳过的文件不需要用 ffmpeg 打开
+    if probe && !screenshot::probe_video(file) {
+        debug!("skipping non-video file: {}", file.display());
+        // 记录到清单中，下次不需要再打开
+        if let Some((manifest, entry)) = manifest {
+            manifest.record(Entry {
+                not_video: true,
+                ..entry
+            })?;
+        }
+        return Ok(());
+    }
+    let result = generate(file, args, &config, &sheet, &output);
+    if let Some((manifest, entry)) = manifest {
+        manifest.record(Entry {
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+            ..entry
+        })?;
+    }
+    result
+}
+
+/// 清单中记录的参数，包括截图参数、输出路径和生成的其他文件
+fn settings_key(sheet: &ContactSheet, args: &cli::Args, output: &Path) -> String {
+    format!(
+        "{}|{}|{:?}|{:?}|{:?}|{}|{}|{:?}",
+        sheet.settings(),
+        output.display(),
+        args.animation_options(),
+        args.teaser_options(),
+        args.sprite_options(),
+        args.json,
+        args.no_sheet,
+        args.export_frames,
+    )
+}
+
+/// 生成并保存截图和其他输出
+fn generate(
+    file: &Path,
+    args: &cli::Args,
+    config: &Config,
+    sheet: &ContactSheet,
+    output: &Path,
+) -> Result<()> {
     #[cfg(target_os = "windows")]
     let should_show = args.show;
     #[cfg(not(target_os = "windows"))]
     let should_show = false;
-    let should_save = !args.no_save && !output.exists();
+    let outputs = SideOutputs::new(file, sheet, args)?;
+    let should_save = !args.no_save && should_generate(file, output, &outputs, sheet, args)?;
     if !should_show && !should_save {
         info!("不需要处理文件 {}, 跳过", output.display());
         return Ok(());
     }
+    // 需要更新时覆盖旧的输出
+    let overwrite = args.update || config.overwrite();
 
     debug!("Generating for file {}", file.display());
-    let mut extractor = frame_extractor::FrameExtractor::new(
-        file,
-        args.num_of_frames(),
-        args.scaled_frame_width(),
-    )?;
-    let info = extractor.info.clone();
-
-    let mut frames = vec![];
-
-    while extractor.extract_frame_to_internal_buffer()? {
-        let frame = &mut extractor.extracted_bgr_frame;
-        let (width, height, line_size) = (frame.width(), frame.height(), frame.stride(0));
-        assert_eq!(width, args.scaled_frame_width());
-        let data = frame.data_mut(0);
-
-        let mat = image_maker::open_frame_data(width as usize, height as usize, line_size, data)?;
-        let time = extractor.extracted_bgr_frame_time;
-        frames.push((mat, time.to_string()));
-    }
-
-    let buf = image_maker::merge_images(frames, info, args)?;
+    let export_dir = args.export_dir(file).filter(|_| !args.no_save);
+    let (frames, info) = match (&export_dir, args.full_resolution) {
+        // 原始尺寸截图一次，单独保存之后再缩小用于拼接
+        (Some(dir), true) => {
+            let (frames, info) = sheet.clone().full_resolution(true).extract_frames(file)?;
+            export_frames(file, &frames, dir, args)?;
+            (sheet.scale_frames(frames)?, info)
+        }
+        (Some(dir), false) => {
+            let (frames, info) = sheet.extract_frames(file)?;
+            export_frames(file, &frames, dir, args)?;
+            (frames, info)
+        }
+        (None, _) => sheet.extract_frames(file)?,
+    };
+    let animation = match (args.animation_options(), outputs.animation) {
+        (Some(options), Some(path)) => {
+            let buf = screenshot::animation::encode_animation(&frames, &options)?;
+            Some((path, buf))
+        }
+        _ => None,
+    };
+    let sidecar = match outputs.sidecar {
+        Some(path) => {
+            let json = sheet.sidecar(&frames, &info)?.to_json()?;
+            Some((path, json.into_bytes()))
+        }
+        None => None,
+    };
+    let buf = if args.no_sheet {
+        None
+    } else if args.no_metadata {
+        Some(sheet.render_frames(frames, info)?)
+    } else {
+        let source = sheet.source_info(file, &info)?;
+        let buf = sheet.render_frames(frames, info)?;
+        Some(embed::embed(buf, &sheet.options.ext, &source)?)
+    };
+    let teaser = match (args.teaser_options(), outputs.teaser) {
+        (Some(options), Some(path)) => Some((path, sheet.render_teaser(file, &options)?)),
+        _ => None,
+    };
+    let sprite = match (args.sprite_options(), outputs.sprite) {
+        (Some(options), Some(paths)) => render_sprite(file, sheet, &options, paths)?,
+        _ => vec![],
+    };
 
     #[cfg(target_os = "windows")]
-    if args.show {
+    if let (true, Some(buf)) = (args.show, &buf) {
         // instead of using the imshow, use system default image viewer
         // make a temp file with the ext but a space-free name
         let filename = output
@@ -91,25 +207,184 @@ fn run(file: &std::path::Path, args: &cli::Args) -> Result<()> {
     if args.no_save {
         info!("image not saved");
     } else {
-        if output.exists() {
-            if args.no_overwrite {
-                info!("图片 {} 已存在, 跳过", output.display());
-                return Ok(());
-            } else {
-                info!("图片 {} 已存在, 覆盖", output.display());
+        if let Some(buf) = buf {
+            save_output(file, output, &buf, overwrite)?;
+        }
+        for (output, buf) in sidecar
+            .into_iter()
+            .chain(animation)
+            .chain(teaser)
+            .chain(sprite)
+        {
+            save_output(file, &output, &buf, overwrite)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// 需要生成的其他文件的路径，没有启用时为空
+struct SideOutputs {
+    sidecar: Option<PathBuf>,
+    animation: Option<PathBuf>,
+    teaser: Option<PathBuf>,
+    /// 雪碧图和 .vtt
+    sprite: Option<(PathBuf, PathBuf)>,
+}
+
+impl SideOutputs {
+    fn new(file: &Path, sheet: &ContactSheet, args: &cli::Args) -> Result<Self> {
+        let layout = sheet.effective_layout();
+        let name = |ext: &str| args.output_name(file, &layout, ext);
+        let sidecar = match (args.json, args.no_sheet) {
+            (true, false) => Some(name("json")?),
+            _ => None,
+        };
+        let animation = match args.animation_options() {
+            Some(options) => {
+                let ext = format!("{}.{}", ANIMATION_MARKER, options.format.ext());
+                Some(name(&ext)?)
+            }
+            None => None,
+        };
+        let teaser = match args.teaser_options() {
+            Some(options) => {
+                let ext = format!("{}.{}", TEASER_MARKER, options.format.ext());
+                Some(name(&ext)?)
             }
+            None => None,
+        };
+        let sprite = match args.sprite_options() {
+            Some(_) => Some((
+                name(&format!("sprite.{}", sheet.options.ext))?,
+                name("vtt")?,
+            )),
+            None => None,
+        };
+        Ok(Self {
+            sidecar,
+            animation,
+            teaser,
+            sprite,
+        })
+    }
+
+    fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        let sprite = self.sprite.iter().flat_map(|(image, vtt)| [image, vtt]);
+        self.sidecar
+            .iter()
+            .chain(&self.animation)
+            .chain(&self.teaser)
+            .chain(sprite)
+    }
+}
+
+/// 返回雪碧图和 .vtt 的 (路径, 数据)
+fn render_sprite(
+    file: &Path,
+    sheet: &ContactSheet,
+    options: &SpriteOptions,
+    (image_path, vtt_path): (PathBuf, PathBuf),
+) -> Result<Vec<(PathBuf, Vec<u8>)>> {
+    let ext = &sheet.options.ext;
+    let sprite = sheet.render_sprite(file, options)?;
+    let image_name = image_path
+        .file_name()
+        .context("sprite filename missing")?
+        .to_string_lossy()
+        .to_string();
+    let image = screenshot::encode_image(&sprite.image, ext)?.to_vec();
+    let vtt = sprite.vtt(&image_name).into_bytes();
+    Ok(vec![(image_path, image), (vtt_path, vtt)])
+}
+
+/// 单独保存每一帧
+fn export_frames(
+    file: &Path,
+    frames: &[(Mat, VideoDuration)],
+    dir: &Path,
+    args: &cli::Args,
+) -> Result<()> {
+    let prefix = file.file_stem().context("input filename missing")?;
+    let paths = screenshot::save_frames(frames, dir, &prefix.to_string_lossy(), &args.frames_ext)?;
+    info!("{} 张截图保存到 {}", paths.len(), dir.display());
+    Ok(())
+}
+
+/// 截图或者需要的其他文件不存在，或者 `--update` 时截图已经过期
+fn should_generate(
+    file: &Path,
+    output: &Path,
+    outputs: &SideOutputs,
+    sheet: &ContactSheet,
+    args: &cli::Args,
+) -> Result<bool> {
+    if args.no_sheet || !output.exists() {
+        return Ok(true);
+    }
+    if let Some(missing) = outputs.paths().find(|path| !path.exists()) {
+        info!("{} 不存在, 重新生成", missing.display());
+        return Ok(true);
+    }
+    if let Some(dir) = args.export_dir(file) {
+        if !has_exported_frames(file, &dir) {
+            info!("{} 中没有单独保存的截图, 重新生成", dir.display());
+            return Ok(true);
         }
+    }
+    if !args.update {
+        return Ok(false);
+    }
+    let current = SourceInfo::from_file(file, None, sheet.settings())?;
+    let buf = std::fs::read(output).with_context(|| format!("read {} failed", output.display()))?;
+    let up_to_date = match embed::read(&buf) {
+        Some(recorded) => recorded.same_source(&current),
+        // 没有写入来源信息时，只能比较保存时设置的修改时间
+        None => {
+            let meta = std::fs::metadata(output)?;
+            FileTime::from_last_modification_time(&meta).unix_seconds() == current.modified
+        }
+    };
+    if up_to_date {
+        debug!("{} is up to date", output.display());
+    } else {
+        info!("视频或参数已改变, 重新生成 {}", output.display());
+    }
+    Ok(!up_to_date)
+}
 
-        let meta = std::fs::metadata(file)?;
-        let mut f = std::fs::File::create(&output)?;
-        f.write_all(buf.as_slice())?;
-        std::mem::drop(f);
-        debug!("缩略图保存到 {}", output.display());
-        // set time
-        use filetime::FileTime;
-        filetime::set_file_mtime(output, FileTime::from_last_modification_time(&meta))?;
+/// `dir` 中是否有 `save_frames` 保存的这个视频的截图
+fn has_exported_frames(file: &Path, dir: &Path) -> bool {
+    let Some(stem) = file.file_stem() else { return false; };
+    let prefix = format!("{}-", stem.to_string_lossy());
+    let Ok(entries) = dir.read_dir() else { return false; };
+    entries
+        .filter_map(|entry| entry.ok())
+        .any(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+}
+
+/// 保存生成的文件，修改时间和视频相同
+fn save_output(file: &Path, output: &Path, buf: &[u8], overwrite: bool) -> Result<()> {
+    if output.exists() {
+        if !overwrite {
+            info!("图片 {} 已存在, 跳过", output.display());
+            return Ok(());
+        } else {
+            info!("图片 {} 已存在, 覆盖", output.display());
+        }
     }
 
+    let meta = std::fs::metadata(file)?;
+    if let Some(dir) = output.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("create dir {} failed", dir.display()))?;
+    }
+    let mut f = std::fs::File::create(output)?;
+    f.write_all(buf)?;
+    std::mem::drop(f);
+    debug!("缩略图保存到 {}", output.display());
+    // set time
+    filetime::set_file_mtime(output, FileTime::from_last_modification_time(&meta))?;
     Ok(())
 }
 
@@ -132,52 +407,104 @@ fn system_open(path: &Path) -> Result<()> {
     Ok(())
 }
 
-fn is_video(path: &Path) -> bool {
+/// 生成的预告片和动图在扩展名前加上标记，处理文件夹时跳过，
+/// 动图和截图使用相同的格式时也不会覆盖截图
+const TEASER_MARKER: &str = "teaser";
+const ANIMATION_MARKER: &str = "anim";
+
+/// 截图、动图、雪碧图和其他生成文件的扩展名，`--probe` 时跳过
+const OUTPUT_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "webp", "bmp", "gif", "apng", "json", "vtt",
+];
+
+/// 文件名是否是 `<视频>.<标记>.<扩展名>` 形式的生成文件
+fn is_generated(path: &Path) -> bool {
+    let stem = path.file_stem().map(Path::new);
+    let marker = stem.and_then(|stem| stem.extension());
+    marker.is_some_and(|marker| marker == TEASER_MARKER || marker == ANIMATION_MARKER)
+}
+
+/// 是否是这个工具生成的文件
+fn is_output(path: &Path) -> bool {
     let ext = path.extension().and_then(|s| s.to_str());
     let Some(ext) = ext else {return false};
-    matches!(
-        ext.to_lowercase().as_str(),
-        "mp4" | "m4v" | "mkv" | "avi" | "webm" | "mov" | "flv" | "ts" | "wmv"
-    )
+    OUTPUT_EXTENSIONS
+        .iter()
+        .any(|output| output.eq_ignore_ascii_case(ext))
+        || is_generated(path)
 }
 
-fn visit_recursive_dir(
-    dir: PathBuf,
-    args: Arc<cli::Args>,
-    error_tx: mpsc::Sender<(PathBuf, anyhow::Error)>,
-) {
-    for entry in dir.read_dir().unwrap() {
-        let entry = entry.unwrap();
-        let path = entry.path();
-        if path.is_file() {
-            let is_video = is_video(&path);
-            if !is_video {
-                debug!("skipping file: {}", path.display());
-                continue;
-            }
-            info!("处理文件 {}", path.display());
-            let error_tx = error_tx.clone();
-            let args = Arc::clone(&args);
+fn is_video(path: &Path, config: &Config) -> bool {
+    let ext = path.extension().and_then(|s| s.to_str());
+    let Some(ext) = ext else {return false};
+    config.is_video_ext(ext) && !is_generated(path)
+}
 
-            let task = move || {
+/// 按路径顺序处理文件夹中的视频，同时最多处理 `--jobs` 个
+fn process_dir(args: &cli::Args, config: &Config, manifest: Option<&Manifest>) -> Result<()> {
+    let overrides = args.overrides();
+    let filter = |path: &Path, config: &Config| {
+        // --probe 时在处理前用 ffmpeg 判断，只跳过生成的文件
+        if args.probe {
+            return !is_output(path);
+        }
+        let config = if args.extensions.is_empty() {
+            config
+        } else {
+            &overrides
+        };
+        is_video(path, config)
+    };
+    let walk = walk::walk(&args.input, config, &args.walk_options()?, filter);
+    let mut errors = walk.errors;
+    if let (true, Some((path, e))) = (args.ignore_error, errors.first()) {
+        bail!("读取 {} 错误: {:#}", path.display(), e);
+    }
+    info!("找到 {} 个文件", walk.files.len());
+
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs)
+        .build()
+        .context("create thread pool failed")?;
+    let failed = AtomicBool::new(false);
+    let mut results: Vec<_> = pool.install(|| {
+        // par_bridge 按顺序取出文件，开始处理的顺序和路径顺序相同
+        walk.files
+            .iter()
+            .enumerate()
+            .par_bridge()
+            .filter_map(|(i, (path, config))| {
+                // --ignore-error 时有文件失败后不再开始新的文件，默认处理所有文件
+                if args.ignore_error && failed.load(Ordering::Relaxed) {
+                    return None;
+                }
+                info!("处理文件 {}", path.display());
                 let t = Instant::now();
-                let run_result = run(&path, &args);
-                match run_result {
+                match run(path, args, config, manifest, args.probe) {
                     Ok(_) => {
                         info!("处理文件成功，耗时 {:?}：{}", t.elapsed(), path.display());
+                        None
                     }
                     Err(e) => {
-                        error_tx.send((path, e)).unwrap();
+                        failed.store(true, Ordering::Relaxed);
+                        Some((i, path.clone(), e))
                     }
                 }
-            };
-            rayon::spawn(task);
-        } else {
-            let e_tx = error_tx.clone();
-            let args = Arc::clone(&args);
-            rayon::spawn(move || {
-                visit_recursive_dir(path, args, e_tx);
-            });
+            })
+            .collect()
+    });
+    results.sort_by_key(|(i, _, _)| *i);
+    errors.extend(results.into_iter().map(|(_, path, e)| (path, e)));
+
+    if args.ignore_error {
+        if let Some((path, e)) = errors.into_iter().next() {
+            bail!("处理文件 {} 错误: {:#}", path.display(), e);
         }
+        return Ok(());
     }
+    info!("处理文件完成, 有 {} 个文件处理失败：", errors.len());
+    for (path, e) in errors.iter() {
+        error!("处理文件 {} 错误: {:#}", path.display(), e);
+    }
+    Ok(())
 }
@@ -0,0 +1,129 @@
+//! 过滤黑屏、纯色和模糊的截图
+
+use serde::Serialize;
+
+/// 截图质量的阈值
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct QualityFilter {
+    /// 平均亮度的下限 (0-255)，低于此值认为是黑屏
+    pub min_brightness: f32,
+    /// 亮度标准差的下限，低于此值认为是纯色画面
+    pub min_contrast: f32,
+    /// 拉普拉斯方差的下限，低于此值认为是模糊画面。0 表示不检查
+    pub min_sharpness: f32,
+    /// 不合格时在附近重试的次数
+    pub retries: u32,
+}
+
+impl Default for QualityFilter {
+    fn default() -> Self {
+        Self {
+            min_brightness: 10.0,
+            min_contrast: 5.0,
+            min_sharpness: 0.0,
+            retries: 3,
+        }
+    }
+}
+
+impl QualityFilter {
+    pub fn accepts(&self, quality: &FrameQuality) -> bool {
+        quality.brightness >= self.min_brightness
+            && quality.contrast >= self.min_contrast
+            && quality.sharpness >= self.min_sharpness
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameQuality {
+    /// 平均亮度
+    pub brightness: f32,
+    /// 亮度标准差
+    pub contrast: f32,
+    /// 拉普拉斯方差
+    pub sharpness: f32,
+}
+
+impl FrameQuality {
+    /// data 是 BGR24 格式，每行 `line_size` 字节
+    pub fn from_bgr(width: usize, height: usize, line_size: usize, data: &[u8]) -> Self {
+        let mut luma = Vec::with_capacity(width * height);
+        for row in 0..height {
+            let row_data = &data[row * line_size..row * line_size + width * 3];
+            luma.extend(
+                row_data
+                    .chunks_exact(3)
+                    .map(|px| 0.114 * px[0] as f32 + 0.587 * px[1] as f32 + 0.299 * px[2] as f32),
+            );
+        }
+        let (brightness, contrast) = mean_std(luma.iter().copied());
+
+        let laplacian = (1..height.saturating_sub(1)).flat_map(|y| {
+            let luma = &luma;
+            (1..width.saturating_sub(1)).map(move |x| {
+                let at = |x: usize, y: usize| luma[y * width + x];
+                at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4.0 * at(x, y)
+            })
+        });
+        let (_, laplacian_std) = mean_std(laplacian);
+
+        Self {
+            brightness,
+            contrast,
+            sharpness: laplacian_std * laplacian_std,
+        }
+    }
+
+    pub fn from_frame(frame: &ffmpeg_next::frame::Video) -> Self {
+        Self::from_bgr(
+            frame.width() as usize,
+            frame.height() as usize,
+            frame.stride(0),
+            frame.data(0),
+        )
+    }
+}
+
+fn mean_std(values: impl Iterator<Item = f32>) -> (f32, f32) {
+    let (mut n, mut sum, mut sum_sq) = (0usize, 0f64, 0f64);
+    for v in values {
+        n += 1;
+        sum += v as f64;
+        sum_sq += (v as f64) * (v as f64);
+    }
+    if n == 0 {
+        return (0.0, 0.0);
+    }
+    let mean = sum / n as f64;
+    let var = (sum_sq / n as f64 - mean * mean).max(0.0);
+    (mean as f32, var.sqrt() as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn black_frame() {
+        let data = vec![0u8; 8 * 8 * 3];
+        let quality = FrameQuality::from_bgr(8, 8, 8 * 3, &data);
+        assert_eq!(quality.brightness, 0.0);
+        assert_eq!(quality.contrast, 0.0);
+        assert_eq!(quality.sharpness, 0.0);
+        assert!(!QualityFilter::default().accepts(&quality));
+    }
+
+    #[test]
+    fn checkerboard_frame() {
+        let data: Vec<u8> = (0..8 * 8)
+            .flat_map(|i| {
+                let v = if (i / 8 + i % 8) % 2 == 0 { 0 } else { 255 };
+                [v, v, v]
+            })
+            .collect();
+        let quality = FrameQuality::from_bgr(8, 8, 8 * 3, &data);
+        assert!((quality.brightness - 127.5).abs() < 0.1);
+        assert!(quality.sharpness > 1000.0);
+        assert!(QualityFilter::default().accepts(&quality));
+    }
+}
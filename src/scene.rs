@@ -0,0 +1,85 @@
+//! 根据画面变化挑选截图
+
+const BINS: usize = 16;
+
+/// BGR 三个通道各自的归一化直方图
+#[derive(Clone)]
+pub struct Histogram([f32; BINS * 3]);
+
+impl Histogram {
+    /// data 是 BGR24 格式，每行 `line_size` 字节
+    pub fn from_bgr(width: usize, height: usize, line_size: usize, data: &[u8]) -> Self {
+        let mut bins = [0f32; BINS * 3];
+        // 隔行隔列采样就足够了
+        for row in (0..height).step_by(2) {
+            let row_data = &data[row * line_size..row * line_size + width * 3];
+            for px in row_data.chunks_exact(3).step_by(2) {
+                for (channel, value) in px.iter().enumerate() {
+                    bins[channel * BINS + *value as usize * BINS / 256] += 1.0;
+                }
+            }
+        }
+        let total = bins[..BINS].iter().sum::<f32>().max(1.0);
+        for bin in bins.iter_mut() {
+            *bin /= total;
+        }
+        Self(bins)
+    }
+
+    pub fn from_frame(frame: &ffmpeg_next::frame::Video) -> Self {
+        Self::from_bgr(
+            frame.width() as usize,
+            frame.height() as usize,
+            frame.stride(0),
+            frame.data(0),
+        )
+    }
+
+    /// 两张图的差异，范围 [0, 1]
+    pub fn distance(&self, other: &Self) -> f32 {
+        let l1: f32 = self
+            .0
+            .iter()
+            .zip(other.0.iter())
+            .map(|(a, b)| (a - b).abs())
+            .sum();
+        l1 / 6.0
+    }
+
+    /// 信息量，纯色画面（比如黑屏）接近 0
+    pub fn entropy(&self) -> f32 {
+        -self
+            .0
+            .iter()
+            .filter(|p| **p > 0.0)
+            .map(|p| p * p.log2())
+            .sum::<f32>()
+            / 3.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn solid(b: u8, g: u8, r: u8) -> Histogram {
+        let data: Vec<u8> = [b, g, r].repeat(8 * 8);
+        Histogram::from_bgr(8, 8, 8 * 3, &data)
+    }
+
+    #[test]
+    fn histogram_distance() {
+        let black = solid(0, 0, 0);
+        let white = solid(255, 255, 255);
+        assert_eq!(black.distance(&black), 0.0);
+        assert!((black.distance(&white) - 1.0).abs() < 1e-6);
+        assert_eq!(black.entropy(), 0.0);
+    }
+
+    #[test]
+    fn histogram_entropy() {
+        let data: Vec<u8> = (0..16 * 16 * 3).map(|i| (i * 7 % 256) as u8).collect();
+        let noisy = Histogram::from_bgr(16, 16, 16 * 3, &data);
+        assert!(noisy.entropy() > solid(10, 20, 30).entropy());
+    }
+}
@@ -0,0 +1,527 @@
+use anyhow::{bail, Context, Result};
+use ffmpeg_next::Rational;
+use opencv::{core::Mat, prelude::*};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::{
+    animation::{self, AnimationOptions},
+    embed::{self, SourceInfo},
+    frame_extractor::{
+        Deinterlace, ExtractOptions, FrameExtractor, FrameSelection, ScalingAlgorithm,
+    },
+    image_maker,
+    info::Info,
+    layout::{RenderOptions, SheetLayout},
+    quality::QualityFilter,
+    sidecar::Sidecar,
+    sprite::{Sprite, SpriteOptions},
+    teaser::{self, TeaserOptions},
+    tonemap::ToneMapping,
+    transform::Crop,
+    utils::{TempDir, VideoDuration},
+};
+
+/// 截图的配置，使用 builder 风格设置参数
+#[derive(Debug, Clone, Default)]
+pub struct ContactSheet {
+    pub layout: SheetLayout,
+    pub options: RenderOptions,
+    pub extract: ExtractOptions,
+}
+
+/// `settings` 的格式，字段改变时增加，旧的截图会重新生成
+const SETTINGS_VERSION: u32 = 1;
+
+/// 写入截图和清单的参数
+#[derive(Serialize)]
+struct Settings<'a> {
+    version: u32,
+    rows: u32,
+    cols: u32,
+    width: u32,
+    space: u32,
+    auto_flip: bool,
+    ext: &'a str,
+    #[cfg(feature = "font")]
+    font: Option<&'a Path>,
+    selection: FrameSelection,
+    quality: Option<&'a QualityFilter>,
+    timestamps: Vec<(i32, i32)>,
+    start: Option<(i32, i32)>,
+    end: Option<(i32, i32)>,
+    accurate_seek: bool,
+    scaling: ScalingAlgorithm,
+    tone_mapping: ToneMapping,
+    hdr_peak: Option<f32>,
+    keep_black_bars: bool,
+    deinterlace: Deinterlace,
+    filter: Option<&'a str>,
+    full_resolution: bool,
+}
+
+fn rational(r: Rational) -> (i32, i32) {
+    (r.numerator(), r.denominator())
+}
+
+impl ContactSheet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(layout: SheetLayout, options: RenderOptions) -> Self {
+        Self {
+            layout,
+            options,
+            extract: ExtractOptions::default(),
+        }
+    }
+
+    /// 纵向数量
+    pub fn rows(mut self, rows: u32) -> Self {
+        self.layout.rows = rows;
+        self
+    }
+    /// 横向数量
+    pub fn cols(mut self, cols: u32) -> Self {
+        self.layout.cols = cols;
+        self
+    }
+    /// 横向尺寸
+    pub fn width(mut self, width: u32) -> Self {
+        self.layout.width = width;
+        self
+    }
+    /// 图片之间的间隔
+    pub fn space(mut self, space: u32) -> Self {
+        self.layout.space = space;
+        self
+    }
+    /// 竖屏视频是否自动交换行列数
+    pub fn auto_flip(mut self, auto_flip: bool) -> Self {
+        self.layout.auto_flip = auto_flip;
+        self
+    }
+    /// 编码格式，如 `jpg`、`png`、`webp`
+    pub fn ext(mut self, ext: impl Into<String>) -> Self {
+        self.options.ext = ext.into();
+        self
+    }
+    /// 使用字体的路径，不指定时使用内置字体
+    #[cfg(feature = "font")]
+    pub fn font(mut self, font: Option<std::path::PathBuf>) -> Self {
+        self.options.font = font;
+        self
+    }
+    /// 截图的选项
+    pub fn extract_options(mut self, extract: ExtractOptions) -> Self {
+        self.extract = extract;
+        self
+    }
+    /// 每个位置如何选择截图
+    pub fn selection(mut self, selection: FrameSelection) -> Self {
+        self.extract.selection = selection;
+        self
+    }
+    /// 指定截图的时间点
+    pub fn timestamps(mut self, timestamps: Vec<Rational>) -> Self {
+        self.extract.timestamps = timestamps;
+        self
+    }
+    /// 截图的时间范围，`end` 为负数时表示距离结尾的时间
+    pub fn range(mut self, start: Option<Rational>, end: Option<Rational>) -> Self {
+        self.extract.start = start;
+        self.extract.end = end;
+        self
+    }
+    /// 解码到准确的时间点，而不是使用之后的关键帧
+    pub fn accurate_seek(mut self, accurate_seek: bool) -> Self {
+        self.extract.accurate_seek = accurate_seek;
+        self
+    }
+    /// 解码器的线程数，0 表示自动
+    pub fn decoder_threads(mut self, threads: usize) -> Self {
+        self.extract.decoder_threads = threads;
+        self
+    }
+    /// 同时截图的数量
+    pub fn parallel(mut self, parallel: usize) -> Self {
+        self.extract.parallel = parallel;
+        self
+    }
+    /// 缩放算法
+    pub fn scaling(mut self, scaling: ScalingAlgorithm) -> Self {
+        self.extract.scaling = scaling;
+        self
+    }
+    /// HDR 视频的色调映射算法和峰值亮度 (nits)
+    pub fn tone_mapping(mut self, tone_mapping: ToneMapping, peak: Option<f32>) -> Self {
+        self.extract.tone_mapping = tone_mapping;
+        self.extract.hdr_peak = peak;
+        self
+    }
+    /// 隔行扫描视频的处理方式
+    pub fn deinterlace(mut self, deinterlace: Deinterlace) -> Self {
+        self.extract.deinterlace = deinterlace;
+        self
+    }
+    /// 解码后、缩放前使用的 ffmpeg filter graph
+    pub fn filter(mut self, filter: Option<String>) -> Self {
+        self.extract.filter = filter;
+        self
+    }
+    /// 截图不缩小，用于单独保存每一帧
+    pub fn full_resolution(mut self, full_resolution: bool) -> Self {
+        self.extract.full_resolution = full_resolution;
+        self
+    }
+    /// 是否保留截图四周的黑边
+    pub fn keep_black_bars(mut self, keep: bool) -> Self {
+        self.extract.keep_black_bars = keep;
+        self
+    }
+    /// 跳过黑屏、纯色和模糊的截图
+    pub fn quality(mut self, quality: Option<QualityFilter>) -> Self {
+        self.extract.quality = quality;
+        self
+    }
+
+    /// 从视频中截取所有帧，返回 (帧, 时间) 和视频信息
+    pub fn extract_frames(&self, input: &Path) -> Result<(Vec<(Mat, VideoDuration)>, Info)> {
+        let (mut frames, info) = self.extract_uncropped_frames(input)?;
+        if !self.extract.keep_black_bars {
+            self.crop_black_bars(&mut frames)?;
+        }
+        Ok((frames, info))
+    }
+
+    /// 所有截图按相同的区域裁掉黑边，再缩放回原来的宽度。原始尺寸时不缩放
+    fn crop_black_bars(&self, frames: &mut [(Mat, VideoDuration)]) -> Result<()> {
+        let Some(first) = frames.first() else { return Ok(()); };
+        let (width, height) = (first.0.cols() as usize, first.0.rows() as usize);
+        let crop = frames
+            .iter()
+            .filter_map(|(mat, _)| {
+                let data = mat.data_bytes().ok()?;
+                Crop::detect(width, height, width * 3, data)
+            })
+            .reduce(Crop::intersect);
+        let Some(crop) = crop.filter(|crop| !crop.is_empty()) else { return Ok(()); };
+        let (_, _, w, h) = crop.rect(width, height);
+        // 剩下的太小，可能是很暗的视频
+        if w < width / 4 || h < height / 4 {
+            debug!("black bars {:?} too large, not cropping", crop);
+            return Ok(());
+        }
+        debug!("cropping black bars {:?}", crop);
+        let width = if self.extract.full_resolution {
+            w
+        } else {
+            width
+        };
+        for (mat, _) in frames.iter_mut() {
+            *mat = image_maker::crop_image(mat, &crop, width as i32)?;
+        }
+        Ok(())
+    }
+
+    /// 把原始尺寸的截图缩小到拼接时的宽度，用于同时单独保存原始尺寸的截图
+    pub fn scale_frames(
+        &self,
+        frames: Vec<(Mat, VideoDuration)>,
+    ) -> Result<Vec<(Mat, VideoDuration)>> {
+        let width = self.layout.scaled_frame_width() as i32;
+        frames
+            .into_iter()
+            .map(|(mat, time)| Ok((image_maker::resize_image(&mat, width)?, time)))
+            .collect()
+    }
+
+    fn extract_uncropped_frames(&self, input: &Path) -> Result<(Vec<(Mat, VideoDuration)>, Info)> {
+        let extractor = self.open_extractor(input)?;
+        let info = extractor.info.clone();
+        let n = extractor.num_of_frames();
+        let workers = (self.extract.parallel as u32).clamp(1, n.max(1));
+        if workers == 1 {
+            return Ok((self.collect_frames(extractor)?, info));
+        }
+        std::mem::drop(extractor);
+
+        // 每个 worker 单独打开文件，负责一段连续的位置
+        debug!("extracting {} frames with {} workers", n, workers);
+        let chunk = n.div_ceil(workers);
+        let mut chunks = (0..workers)
+            .into_par_iter()
+            .map(|w| {
+                let extractor = self
+                    .open_extractor(input)?
+                    .with_slots(w * chunk..(w + 1) * chunk);
+                self.collect_frames(extractor)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // 相邻两段可能截到同一帧，这时重新截取后一段的第一帧
+        for w in 1..chunks.len() {
+            let (Some((_, last)), Some((_, first))) = (chunks[w - 1].last(), chunks[w].first())
+            else {
+                continue;
+            };
+            if first.0 != last.0 {
+                continue;
+            }
+            let last = last.0;
+            let w = w as u32;
+            let mut extractor = self
+                .open_extractor(input)?
+                .with_slots(w * chunk..(w + 1) * chunk);
+            extractor.skip_time(last);
+            if extractor.extract_frame_to_internal_buffer()? {
+                chunks[w as usize][0] = self.current_frame(&mut extractor)?;
+            }
+        }
+        Ok((chunks.into_iter().flatten().collect(), info))
+    }
+
+    fn open_extractor(&self, input: &Path) -> Result<FrameExtractor> {
+        FrameExtractor::new(
+            input,
+            self.layout.num_of_frames(),
+            self.layout.scaled_frame_width(),
+            self.extract.clone(),
+        )
+    }
+
+    fn collect_frames(&self, mut extractor: FrameExtractor) -> Result<Vec<(Mat, VideoDuration)>> {
+        let mut frames = vec![];
+        while extractor.extract_frame_to_internal_buffer()? {
+            frames.push(self.current_frame(&mut extractor)?);
+        }
+        Ok(frames)
+    }
+
+    /// 复制 extractor 刚截取的帧
+    fn current_frame(&self, extractor: &mut FrameExtractor) -> Result<(Mat, VideoDuration)> {
+        let frame = &mut extractor.extracted_bgr_frame;
+        let (width, height, line_size) = (frame.width(), frame.height(), frame.stride(0));
+        if !self.extract.full_resolution {
+            assert_eq!(width, self.layout.scaled_frame_width());
+        }
+        let data = frame.data_mut(0);
+
+        let mat = image_maker::open_frame_data(width as usize, height as usize, line_size, data)?;
+        Ok((mat, extractor.extracted_bgr_frame_time))
+    }
+
+    /// 指定时间点时，行数随时间点数量变化
+    pub fn effective_layout(&self) -> SheetLayout {
+        let mut layout = self.layout.clone();
+        let n = self.extract.timestamps.len() as u32;
+        if n > 0 {
+            layout.rows = n.div_ceil(layout.cols);
+        }
+        layout
+    }
+
+    /// 生成截图，返回未编码的 BGR 图像
+    pub fn render_mat(&self, input: impl AsRef<Path>) -> Result<Mat> {
+        let (frames, info) = self.extract_frames(input.as_ref())?;
+        image_maker::merge_images(frames, info, &self.effective_layout(), &self.options)
+    }
+
+    /// 生成截图，返回按 `ext` 编码并写入来源信息后的数据
+    pub fn render_path(&self, input: impl AsRef<Path>) -> Result<Vec<u8>> {
+        let input = input.as_ref();
+        let (frames, info) = self.extract_frames(input)?;
+        let source = self.source_info(input, &info)?;
+        let buf = self.render_frames(frames, info)?;
+        embed::embed(buf, &self.options.ext, &source)
+    }
+
+    /// 用 `extract_frames` 截取的帧生成截图，返回按 `ext` 编码后的数据
+    pub fn render_frames(&self, frames: Vec<(Mat, VideoDuration)>, info: Info) -> Result<Vec<u8>> {
+        let canvas =
+            image_maker::merge_images(frames, info, &self.effective_layout(), &self.options)?;
+        let buf = image_maker::encode_image(&canvas, &self.options.ext)?;
+        Ok(buf.to_vec())
+    }
+
+    /// 写入图片的来源信息，包括视频的路径、大小、修改时间、时长和截图参数
+    pub fn source_info(&self, input: &Path, info: &Info) -> Result<SourceInfo> {
+        #[cfg(feature = "info")]
+        let duration = Some(f64::from(info.video_duration));
+        #[cfg(not(feature = "info"))]
+        let duration = {
+            let _ = info;
+            None
+        };
+        SourceInfo::from_file(input, duration, self.settings())
+    }
+
+    /// 影响截图内容的参数，改变后需要重新生成。线程数只影响速度，不包括在内
+    pub fn settings(&self) -> String {
+        let layout = self.effective_layout();
+        let extract = &self.extract;
+        let settings = Settings {
+            version: SETTINGS_VERSION,
+            rows: layout.rows,
+            cols: layout.cols,
+            width: layout.width,
+            space: layout.space,
+            auto_flip: layout.auto_flip,
+            ext: &self.options.ext,
+            #[cfg(feature = "font")]
+            font: self.options.font.as_deref(),
+            selection: extract.selection,
+            quality: extract.quality.as_ref(),
+            timestamps: extract.timestamps.iter().map(|&t| rational(t)).collect(),
+            start: extract.start.map(rational),
+            end: extract.end.map(rational),
+            accurate_seek: extract.accurate_seek,
+            scaling: extract.scaling,
+            tone_mapping: extract.tone_mapping,
+            hdr_peak: extract.hdr_peak,
+            keep_black_bars: extract.keep_black_bars,
+            deinterlace: extract.deinterlace,
+            filter: extract.filter.as_deref(),
+            full_resolution: extract.full_resolution,
+        };
+        // 只有基本类型，不会失败
+        serde_json::to_string(&settings).expect("serialize settings failed")
+    }
+
+    /// `render_frames` 生成的截图中每张截图的位置和时间
+    pub fn sidecar(&self, frames: &[(Mat, VideoDuration)], info: &Info) -> Result<Sidecar> {
+        let Some((first, _)) = frames.first() else { bail!("没有截图生成") };
+        let tile_size = (first.cols() as u32, first.rows() as u32);
+        let times: Vec<_> = frames.iter().map(|(_, time)| *time).collect();
+        Ok(Sidecar::new(
+            &times,
+            tile_size,
+            info,
+            &self.effective_layout(),
+        ))
+    }
+
+    /// 把每一帧单独保存到 `dir`，文件名为 `<视频文件名>-<时间>.<ext>`，返回保存的路径
+    pub fn export_frames(
+        &self,
+        input: impl AsRef<Path>,
+        dir: impl AsRef<Path>,
+        ext: &str,
+    ) -> Result<Vec<PathBuf>> {
+        let input = input.as_ref();
+        let (frames, _) = self.extract_frames(input)?;
+        let prefix = input.file_stem().context("input filename missing")?;
+        image_maker::save_frames(&frames, dir.as_ref(), &prefix.to_string_lossy(), ext)
+    }
+
+    /// 按固定间隔截图，生成进度条预览用的雪碧图
+    pub fn render_sprite(
+        &self,
+        input: impl AsRef<Path>,
+        options: &SpriteOptions,
+    ) -> Result<Sprite> {
+        let input = input.as_ref();
+        if options.interval <= Rational::new(0, 1) {
+            bail!(
+                "invalid sprite interval {}",
+                VideoDuration(options.interval)
+            );
+        }
+        let (start, end) = self.open_extractor(input)?.range();
+        let mut timestamps = vec![];
+        let mut t = start;
+        while t < end {
+            timestamps.push(t);
+            t = t + options.interval;
+        }
+
+        // 每列一张截图，没有间隔，截图宽度就是 tile_width
+        let sheet = self
+            .clone()
+            .cols(1)
+            .space(0)
+            .width(options.tile_width)
+            .timestamps(timestamps.clone())
+            .accurate_seek(true);
+        let (frames, _) = sheet.extract_frames(input)?;
+
+        // 截图失败时，前一张截图覆盖它的时间段
+        let mut slots = vec![];
+        for (_, time) in frames.iter() {
+            let k = f64::from((time.0 - start) / options.interval).floor() as usize;
+            slots.push(k.min(timestamps.len() - 1));
+        }
+        let times: Vec<_> = slots
+            .iter()
+            .enumerate()
+            .map(|(i, k)| {
+                let slot_end = match slots.get(i + 1) {
+                    Some(next) => timestamps[*next],
+                    None => end,
+                };
+                (timestamps[*k], slot_end)
+            })
+            .collect();
+        let images: Vec<_> = frames.into_iter().map(|(mat, _)| mat).collect();
+        Sprite::compose(&images, &times, options.cols)
+    }
+
+    /// 生成轮播截图的动图，返回编码后的数据
+    pub fn render_animation(
+        &self,
+        input: impl AsRef<Path>,
+        options: &AnimationOptions,
+    ) -> Result<Vec<u8>> {
+        let (frames, _) = self.extract_frames(input.as_ref())?;
+        animation::encode_animation(&frames, options)
+    }
+
+    /// 从每个截图位置截取一段，生成预告片，返回编码后的数据
+    pub fn render_teaser(
+        &self,
+        input: impl AsRef<Path>,
+        options: &TeaserOptions,
+    ) -> Result<Vec<u8>> {
+        let extractor = self.open_extractor(input.as_ref())?;
+        teaser::encode_teaser(extractor, options)
+    }
+
+    /// 从 reader 生成截图。ffmpeg 需要 seek，所以会先把数据写到临时文件。
+    /// `file_name` 用于显示在视频信息里
+    pub fn render_reader(&self, mut reader: impl Read, file_name: &str) -> Result<Vec<u8>> {
+        let file_name = Path::new(file_name)
+            .file_name()
+            .context("invalid file name")?;
+        let dir = TempDir::new()?;
+        let tempfile = dir.join(file_name);
+        let mut f = std::fs::File::create(&tempfile).context("create temp file failed")?;
+        std::io::copy(&mut reader, &mut f).context("write temp file failed")?;
+        std::mem::drop(f);
+        // 临时文件的路径没有意义，不写入来源信息
+        let (frames, info) = self.extract_frames(&tempfile)?;
+        self.render_frames(frames, info)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_key() {
+        let sheet = ContactSheet::new();
+        let settings = sheet.settings();
+        assert!(settings.starts_with(r#"{"version":1,"rows":5,"cols":3,"#));
+
+        let mut threaded = sheet.clone();
+        threaded.extract.parallel = 4;
+        threaded.extract.decoder_threads = 2;
+        assert_eq!(threaded.settings(), settings);
+
+        let mut skip_intro = sheet;
+        skip_intro.extract.start = Some(Rational::new(90, 1));
+        assert_ne!(skip_intro.settings(), settings);
+    }
+}
@@ -0,0 +1,136 @@
+//! 和截图一起保存的 JSON，记录每张截图的位置和时间，方便索引
+
+use serde::Serialize;
+
+use crate::{info::Info, layout::SheetLayout, utils::VideoDuration};
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Sidecar {
+    /// 视频信息，没有启用 `info` feature 时为空
+    pub info: Option<VideoInfo>,
+    pub layout: LayoutInfo,
+    pub tiles: Vec<Tile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VideoInfo {
+    pub file_name: String,
+    pub file_size: usize,
+    pub width: u32,
+    pub height: u32,
+    /// 秒
+    pub duration: f64,
+    pub codec: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LayoutInfo {
+    /// 实际的行数，竖屏视频可能和设置的不同
+    pub rows: u32,
+    pub cols: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub space: u32,
+    /// 上方信息区域的高度
+    pub header_height: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// 解码出的帧的时间 (秒)
+    pub pts: f64,
+    /// 截图上显示的时间
+    pub time: String,
+}
+
+impl Sidecar {
+    /// 和 `merge_images` 使用相同的排版计算每张截图的位置
+    pub fn new(
+        times: &[VideoDuration],
+        tile_size: (u32, u32),
+        info: &Info,
+        layout: &SheetLayout,
+    ) -> Self {
+        let (tile_width, tile_height) = tile_size;
+        let (rows, cols) = layout.grid(tile_width, tile_height);
+        let header_height = crate::info::info_area_height(layout);
+        Self {
+            info: video_info(info),
+            layout: LayoutInfo {
+                rows,
+                cols,
+                tile_width,
+                tile_height,
+                space: layout.space,
+                header_height,
+            },
+            tiles: tiles(times, tile_size, layout, header_height),
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+}
+
+fn tiles(
+    times: &[VideoDuration],
+    (tile_width, tile_height): (u32, u32),
+    layout: &SheetLayout,
+    top: u32,
+) -> Vec<Tile> {
+    layout
+        .tile_rects(tile_width, tile_height, times.len(), top)
+        .into_iter()
+        .zip(times)
+        .map(|((x, y, width, height), time)| Tile {
+            x,
+            y,
+            width,
+            height,
+            pts: f64::from(time.0),
+            time: time.to_string(),
+        })
+        .collect()
+}
+
+#[cfg(feature = "info")]
+fn video_info(info: &Info) -> Option<VideoInfo> {
+    Some(VideoInfo {
+        file_name: info.file_name.clone(),
+        file_size: info.file_size,
+        width: info.video_width,
+        height: info.video_height,
+        duration: f64::from(info.video_duration),
+        codec: info.video_codec.name().to_string(),
+    })
+}
+
+#[cfg(not(feature = "info"))]
+fn video_info(_: &Info) -> Option<VideoInfo> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ffmpeg_next::Rational;
+
+    #[test]
+    fn tiles_follow_layout() {
+        let times = [
+            VideoDuration(Rational::new(1, 1)),
+            VideoDuration(Rational::new(5, 2)),
+        ];
+        let layout = SheetLayout::default();
+        let tiles = tiles(&times, (100, 50), &layout, 30);
+        assert_eq!(tiles.len(), 2);
+        assert_eq!((tiles[1].x, tiles[1].y), (120, 40));
+        assert_eq!(tiles[1].pts, 2.5);
+        assert_eq!(tiles[1].time, "00:02.500");
+    }
+}
@@ -0,0 +1,132 @@
+//! 网页播放器进度条预览用的雪碧图和 WebVTT 文件
+
+use anyhow::{bail, Result};
+use ffmpeg_next::Rational;
+use opencv::{
+    core::{self as cv_core, Mat, Rect},
+    prelude::*,
+};
+use std::fmt::Write as _;
+
+/// 雪碧图的选项
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpriteOptions {
+    /// 截图的间隔
+    pub interval: Rational,
+    /// 每张截图的宽度
+    pub tile_width: u32,
+    /// 每行的截图数量
+    pub cols: u32,
+}
+
+impl Default for SpriteOptions {
+    fn default() -> Self {
+        Self {
+            interval: Rational::new(10, 1),
+            tile_width: 160,
+            cols: 10,
+        }
+    }
+}
+
+/// 生成的雪碧图和对应的 WebVTT
+pub struct Sprite {
+    /// 未编码的雪碧图
+    pub image: Mat,
+    /// 每张截图的 (开始时间, 结束时间, 在雪碧图中的位置)
+    pub cues: Vec<(Rational, Rational, Rect)>,
+}
+
+impl Sprite {
+    /// 没有边框和间隔地拼接截图，`times` 是每张截图对应的时间段
+    pub fn compose(images: &[Mat], times: &[(Rational, Rational)], cols: u32) -> Result<Self> {
+        let Some(first) = images.first() else { bail!("没有截图生成") };
+        let (w, h) = (first.cols(), first.rows());
+        let cols = (cols as usize).clamp(1, images.len());
+        let rows = images.len().div_ceil(cols);
+        let image = Mat::new_rows_cols_with_default(
+            h * rows as i32,
+            w * cols as i32,
+            cv_core::CV_8UC3,
+            cv_core::Scalar::all(0.),
+        )?;
+        let mut cues = Vec::with_capacity(images.len());
+        for (i, (tile, (start, end))) in images.iter().zip(times).enumerate() {
+            let pos = Rect::new(w * (i % cols) as i32, h * (i / cols) as i32, w, h);
+            let mut roi = Mat::roi(&image, pos)?;
+            tile.copy_to(&mut roi)?;
+            cues.push((*start, *end, pos));
+        }
+        Ok(Self { image, cues })
+    }
+
+    /// 生成 WebVTT，`image_url` 是雪碧图相对于 .vtt 文件的地址
+    pub fn vtt(&self, image_url: &str) -> String {
+        vtt(&self.cues, image_url)
+    }
+}
+
+fn vtt(cues: &[(Rational, Rational, Rect)], image_url: &str) -> String {
+    let mut s = String::from("WEBVTT\n");
+    for (start, end, pos) in cues {
+        write!(
+            s,
+            "\n{} --> {}\n{}#xywh={},{},{},{}\n",
+            vtt_time(*start),
+            vtt_time(*end),
+            image_url,
+            pos.x,
+            pos.y,
+            pos.width,
+            pos.height
+        )
+        .unwrap();
+    }
+    s
+}
+
+/// WebVTT 的时间格式 `HH:MM:SS.mmm`
+fn vtt_time(t: Rational) -> String {
+    let ms = (f64::from(t) * 1000.0).round().max(0.0) as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        ms / 3_600_000,
+        ms / 60_000 % 60,
+        ms / 1000 % 60,
+        ms % 1000
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_vtt_time() {
+        assert_eq!(vtt_time(Rational::new(0, 1)), "00:00:00.000");
+        assert_eq!(vtt_time(Rational::new(3723, 2)), "00:31:01.500");
+        assert_eq!(vtt_time(Rational::new(7200, 1)), "02:00:00.000");
+    }
+
+    #[test]
+    fn format_vtt() {
+        let cues = [
+            (
+                Rational::new(0, 1),
+                Rational::new(10, 1),
+                Rect::new(0, 0, 160, 90),
+            ),
+            (
+                Rational::new(10, 1),
+                Rational::new(15, 1),
+                Rect::new(160, 0, 160, 90),
+            ),
+        ];
+        assert_eq!(
+            vtt(&cues, "a.jpg"),
+            "WEBVTT\n\n\
+             00:00:00.000 --> 00:00:10.000\na.jpg#xywh=0,0,160,90\n\n\
+             00:00:10.000 --> 00:00:15.000\na.jpg#xywh=160,0,160,90\n"
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! 从每个截图位置截取一小段，拼接成预告片
+
+use anyhow::{bail, Context as _, Result};
+use ffmpeg_next::Rational;
+
+use crate::{
+    encode::{EncodeSettings, VideoWriter},
+    frame_extractor::FrameExtractor,
+    utils::TempDir,
+};
+
+/// 预告片的格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TeaserFormat {
+    /// H.264
+    Mp4,
+    /// VP9
+    WebM,
+}
+
+impl TeaserFormat {
+    /// 输出文件的扩展名
+    pub fn ext(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::WebM => "webm",
+        }
+    }
+
+    fn settings(
+        self,
+    ) -> (
+        &'static str,
+        &'static str,
+        &'static [(&'static str, &'static str)],
+    ) {
+        // (muxer, codec, codec options)
+        match self {
+            Self::Mp4 => ("mp4", "libx264", &[("crf", "28"), ("preset", "veryfast")]),
+            Self::WebM => ("webm", "libvpx-vp9", &[("crf", "36"), ("b", "0")]),
+        }
+    }
+}
+
+impl std::str::FromStr for TeaserFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.to_lowercase().as_str() {
+            "mp4" => Self::Mp4,
+            "webm" => Self::WebM,
+            _ => bail!("unknown teaser format {}", s),
+        })
+    }
+}
+
+/// 预告片的选项
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TeaserOptions {
+    pub format: TeaserFormat,
+    /// 每一段的长度 (秒)
+    pub segment_length: Rational,
+}
+
+impl Default for TeaserOptions {
+    fn default() -> Self {
+        Self {
+            format: TeaserFormat::Mp4,
+            segment_length: Rational::new(1, 1),
+        }
+    }
+}
+
+/// 每个位置截取一段，编码为一个视频，返回编码后的数据
+pub fn encode_teaser(mut extractor: FrameExtractor, options: &TeaserOptions) -> Result<Vec<u8>> {
+    let (muxer, codec, codec_options) = options.format.settings();
+    // 毫秒
+    let time_base = Rational::new(1, 1000);
+    let segment_ms = (f64::from(options.segment_length) * 1000.0) as i64;
+
+    let dir = TempDir::new()?;
+    let path = dir.join(format!("teaser.{}", options.format.ext()));
+    let mut writer: Option<VideoWriter> = None;
+    let mut last_pts = -1;
+    for i in 0..extractor.num_of_frames() {
+        let offset = i as i64 * segment_ms;
+        extractor.extract_clip(i, options.segment_length, |frame, time| {
+            let pts = offset + (f64::from(time) * 1000.0) as i64;
+            // 保证 pts 递增
+            if pts <= last_pts {
+                return Ok(());
+            }
+            last_pts = pts;
+            let writer = match &mut writer {
+                Some(writer) => writer,
+                None => {
+                    let settings = EncodeSettings {
+                        muxer,
+                        codec,
+                        // yuv420p 要求宽高都是偶数
+                        filter: "scale=trunc(iw/2)*2:trunc(ih/2)*2,format=yuv420p",
+                        codec_options,
+                        width: frame.width(),
+                        height: frame.height(),
+                        time_base,
+                    };
+                    writer.insert(VideoWriter::create(&path, &settings)?)
+                }
+            };
+            writer.write(frame.data(0), frame.stride(0), pts)
+        })?;
+        debug!("teaser segment {} done", i);
+    }
+    let Some(writer) = writer else { bail!("没有截取到视频片段") };
+    writer.finish()?;
+    std::fs::read(&path).context("read teaser failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_format() {
+        assert_eq!("MP4".parse::<TeaserFormat>().unwrap(), TeaserFormat::Mp4);
+        assert_eq!("webm".parse::<TeaserFormat>().unwrap().ext(), "webm");
+        assert!("gif".parse::<TeaserFormat>().is_err());
+    }
+}
@@ -0,0 +1,240 @@
+//! HDR (PQ / HLG) 转 SDR 的色调映射
+
+use serde::Serialize;
+
+/// SDR 参考白的亮度 (nits)
+const SDR_WHITE: f32 = 100.0;
+
+/// 色调映射算法
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ToneMapping {
+    /// 不做映射，HDR 视频会发灰
+    None,
+    Reinhard,
+    Hable,
+    /// ITU-R BT.2390 EETF
+    #[default]
+    Bt2390,
+}
+
+impl std::str::FromStr for ToneMapping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s.to_lowercase().as_str() {
+            "none" => Self::None,
+            "reinhard" => Self::Reinhard,
+            "hable" => Self::Hable,
+            "bt2390" | "bt.2390" => Self::Bt2390,
+            _ => anyhow::bail!("unknown tone mapping {}", s),
+        })
+    }
+}
+
+/// HDR 的传输特性
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transfer {
+    /// SMPTE ST 2084 (HDR10)
+    Pq,
+    /// ARIB STD-B67
+    Hlg,
+}
+
+impl Transfer {
+    pub fn detect(trc: ffmpeg_next::util::color::TransferCharacteristic) -> Option<Self> {
+        use ffmpeg_next::util::color::TransferCharacteristic;
+        match trc {
+            TransferCharacteristic::SMPTE2084 => Some(Self::Pq),
+            TransferCharacteristic::ARIB_STD_B67 => Some(Self::Hlg),
+            _ => None,
+        }
+    }
+
+    /// 非线性信号 [0, 1] 转换为显示亮度 (nits)
+    fn to_nits(self, v: f32) -> f32 {
+        match self {
+            Self::Pq => pq_eotf(v) * 10000.0,
+            Self::Hlg => {
+                const A: f32 = 0.178_832_77;
+                const B: f32 = 0.284_668_92;
+                const C: f32 = 0.559_910_7;
+                let scene = if v <= 0.5 {
+                    v * v / 3.0
+                } else {
+                    (((v - C) / A).exp() + B) / 12.0
+                };
+                // 1000 nits 显示器的 OOTF，system gamma 1.2
+                1000.0 * scene.powf(1.2)
+            }
+        }
+    }
+}
+
+const PQ_M1: f32 = 2610.0 / 16384.0;
+const PQ_M2: f32 = 2523.0 / 4096.0 * 128.0;
+const PQ_C1: f32 = 3424.0 / 4096.0;
+const PQ_C2: f32 = 2413.0 / 4096.0 * 32.0;
+const PQ_C3: f32 = 2392.0 / 4096.0 * 32.0;
+
+/// PQ 信号转换为相对 10000 nits 的线性亮度
+fn pq_eotf(v: f32) -> f32 {
+    let e = v.max(0.0).powf(1.0 / PQ_M2);
+    ((e - PQ_C1).max(0.0) / (PQ_C2 - PQ_C3 * e)).powf(1.0 / PQ_M1)
+}
+
+/// 相对 10000 nits 的线性亮度转换为 PQ 信号
+fn pq_inverse_eotf(l: f32) -> f32 {
+    let l = l.max(0.0).powf(PQ_M1);
+    ((PQ_C1 + PQ_C2 * l) / (1.0 + PQ_C3 * l)).powf(PQ_M2)
+}
+
+/// BT.2020 到 BT.709 的线性 RGB 转换矩阵
+const BT2020_TO_BT709: [[f32; 3]; 3] = [
+    [1.6605, -0.5876, -0.0728],
+    [-0.1246, 1.1329, -0.0083],
+    [-0.0182, -0.1006, 1.1187],
+];
+
+pub struct ToneMapper {
+    algorithm: ToneMapping,
+    /// 以 SDR 参考白为 1 的峰值亮度
+    peak: f32,
+    /// 16 bit 信号到以 SDR 参考白为 1 的线性亮度
+    linear: Vec<f32>,
+}
+
+impl ToneMapper {
+    /// `peak_nits` 是视频的峰值亮度，一般为 1000
+    pub fn new(transfer: Transfer, algorithm: ToneMapping, peak_nits: f32) -> Self {
+        let linear = (0..=u16::MAX)
+            .map(|v| transfer.to_nits(v as f32 / u16::MAX as f32) / SDR_WHITE)
+            .collect();
+        Self {
+            algorithm,
+            peak: peak_nits / SDR_WHITE,
+            linear,
+        }
+    }
+
+    /// 以 SDR 参考白为 1 的线性亮度映射到 [0, 1]
+    fn map(&self, x: f32) -> f32 {
+        match self.algorithm {
+            ToneMapping::None => x,
+            ToneMapping::Reinhard => {
+                let white = self.peak;
+                x * (1.0 + x / (white * white)) / (1.0 + x)
+            }
+            ToneMapping::Hable => {
+                fn hable(x: f32) -> f32 {
+                    const A: f32 = 0.15;
+                    const B: f32 = 0.50;
+                    const C: f32 = 0.10;
+                    const D: f32 = 0.20;
+                    const E: f32 = 0.02;
+                    const F: f32 = 0.30;
+                    (x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F) - E / F
+                }
+                hable(x) / hable(self.peak)
+            }
+            ToneMapping::Bt2390 => {
+                // 在 PQ 域中做映射，源范围 [0, peak]，目标范围 [0, 1]
+                let src_max = pq_inverse_eotf(self.peak * SDR_WHITE / 10000.0);
+                let dst_max = pq_inverse_eotf(SDR_WHITE / 10000.0) / src_max;
+                let e = pq_inverse_eotf(x * SDR_WHITE / 10000.0) / src_max;
+                let ks = 1.5 * dst_max - 0.5;
+                let e = if e < ks {
+                    e
+                } else {
+                    let t = ((e - ks) / (1.0 - ks)).min(1.0);
+                    let (t2, t3) = (t * t, t * t * t);
+                    (2.0 * t3 - 3.0 * t2 + 1.0) * ks
+                        + (t3 - 2.0 * t2 + t) * (1.0 - ks)
+                        + (-2.0 * t3 + 3.0 * t2) * dst_max
+                };
+                pq_eotf(e * src_max) * 10000.0 / SDR_WHITE
+            }
+        }
+    }
+
+    /// 把 BGR48LE 的 HDR 图像转换为 BGR24 的 SDR 图像
+    pub fn convert(
+        &self,
+        width: usize,
+        height: usize,
+        src: &[u8],
+        src_line_size: usize,
+        dst: &mut [u8],
+        dst_line_size: usize,
+    ) {
+        for row in 0..height {
+            let src_row = &src[row * src_line_size..row * src_line_size + width * 6];
+            let dst_row = &mut dst[row * dst_line_size..row * dst_line_size + width * 3];
+            for (src_px, dst_px) in src_row.chunks_exact(6).zip(dst_row.chunks_exact_mut(3)) {
+                let channel = |i: usize| {
+                    let v = u16::from_le_bytes([src_px[2 * i], src_px[2 * i + 1]]);
+                    self.linear[v as usize]
+                };
+                let (b, g, r) = (channel(0), channel(1), channel(2));
+                let m = &BT2020_TO_BT709;
+                let rgb = [
+                    m[0][0] * r + m[0][1] * g + m[0][2] * b,
+                    m[1][0] * r + m[1][1] * g + m[1][2] * b,
+                    m[2][0] * r + m[2][1] * g + m[2][2] * b,
+                ]
+                .map(|c| c.max(0.0));
+                // 按最大的通道映射，保持色相
+                let max = rgb[0].max(rgb[1]).max(rgb[2]);
+                let scale = if max > 0.0 { self.map(max) / max } else { 0.0 };
+                let [r, g, b] = rgb.map(|c| {
+                    // BT.1886 显示 gamma 2.4
+                    let c = (c * scale).clamp(0.0, 1.0).powf(1.0 / 2.4);
+                    (c * 255.0).round() as u8
+                });
+                dst_px.copy_from_slice(&[b, g, r]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pq_roundtrip() {
+        for l in [0.0, 0.01, 0.1, 0.5, 1.0] {
+            assert!((pq_eotf(pq_inverse_eotf(l)) - l).abs() < 1e-4);
+        }
+        // PQ 0.508 约等于 100 nits
+        assert!((pq_eotf(0.508) * 10000.0 - 100.0).abs() < 2.0);
+    }
+
+    #[test]
+    fn tone_mapping_is_monotonic() {
+        for algorithm in [
+            ToneMapping::Reinhard,
+            ToneMapping::Hable,
+            ToneMapping::Bt2390,
+        ] {
+            let mapper = ToneMapper::new(Transfer::Pq, algorithm, 1000.0);
+            let mut last = 0.0;
+            for i in 0..=100 {
+                let y = mapper.map(i as f32 / 10.0);
+                assert!(y >= last - 1e-6, "{:?} not monotonic at {}", algorithm, i);
+                assert!(y <= 1.0 + 1e-4, "{:?} out of range at {}", algorithm, i);
+                last = y;
+            }
+        }
+    }
+
+    #[test]
+    fn convert_black_and_white() {
+        let mapper = ToneMapper::new(Transfer::Pq, ToneMapping::Bt2390, 1000.0);
+        let src = [[0u8; 6], [0xFF; 6]].concat();
+        let mut dst = [0u8; 6];
+        mapper.convert(2, 1, &src, 12, &mut dst, 6);
+        assert_eq!(&dst[..3], &[0, 0, 0]);
+        assert_eq!(&dst[3..], &[255, 255, 255]);
+    }
+}
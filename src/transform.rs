@@ -0,0 +1,169 @@
+//! 截图的几何变换
+
+/// 顺时针旋转的角度
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    None,
+    Cw90,
+    Cw180,
+    Cw270,
+}
+
+impl Rotation {
+    /// 从角度（顺时针）转换，只支持 90 度的倍数
+    pub fn from_degrees(degrees: f64) -> Self {
+        match (degrees.round() as i64).rem_euclid(360) {
+            45..=134 => Self::Cw90,
+            135..=224 => Self::Cw180,
+            225..=314 => Self::Cw270,
+            _ => Self::None,
+        }
+    }
+
+    /// 旋转后宽高是否互换
+    pub fn is_transposed(self) -> bool {
+        matches!(self, Self::Cw90 | Self::Cw270)
+    }
+}
+
+/// 旋转 BGR24 图像，`width` 和 `height` 是旋转前的尺寸
+pub fn rotate_bgr(
+    rotation: Rotation,
+    width: usize,
+    height: usize,
+    src: &[u8],
+    src_line_size: usize,
+    dst: &mut [u8],
+    dst_line_size: usize,
+) {
+    for y in 0..height {
+        for x in 0..width {
+            let (dx, dy) = match rotation {
+                Rotation::None => (x, y),
+                Rotation::Cw90 => (height - 1 - y, x),
+                Rotation::Cw180 => (width - 1 - x, height - 1 - y),
+                Rotation::Cw270 => (y, width - 1 - x),
+            };
+            let s = y * src_line_size + x * 3;
+            let d = dy * dst_line_size + dx * 3;
+            dst[d..d + 3].copy_from_slice(&src[s..s + 3]);
+        }
+    }
+}
+
+/// 黑边的判断阈值，一行（列）的平均亮度不超过此值认为是黑色
+const BLACK_LIMIT: f32 = 24.0;
+
+/// 四周要裁掉的像素数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Crop {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Crop {
+    /// 检测 BGR24 图像四周的黑边。整张图都是黑色时返回 `None`
+    pub fn detect(width: usize, height: usize, line_size: usize, data: &[u8]) -> Option<Self> {
+        let luma = |x: usize, y: usize| {
+            let px = &data[y * line_size + x * 3..];
+            0.114 * px[0] as f32 + 0.587 * px[1] as f32 + 0.299 * px[2] as f32
+        };
+        let row_is_black =
+            |y: usize| (0..width).map(|x| luma(x, y)).sum::<f32>() <= BLACK_LIMIT * width as f32;
+        let col_is_black =
+            |x: usize| (0..height).map(|y| luma(x, y)).sum::<f32>() <= BLACK_LIMIT * height as f32;
+
+        let top = (0..height).position(|y| !row_is_black(y))?;
+        let bottom = (0..height).rev().position(|y| !row_is_black(y))?;
+        let left = (0..width).position(|x| !col_is_black(x))?;
+        let right = (0..width).rev().position(|x| !col_is_black(x))?;
+        Some(Self {
+            top,
+            bottom,
+            left,
+            right,
+        })
+    }
+
+    /// 取两者都是黑边的部分，保证不会裁掉任何一张图的内容
+    pub fn intersect(self, other: Self) -> Self {
+        Self {
+            top: self.top.min(other.top),
+            bottom: self.bottom.min(other.bottom),
+            left: self.left.min(other.left),
+            right: self.right.min(other.right),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// 裁剪后的 (x, y, 宽, 高)
+    pub fn rect(&self, width: usize, height: usize) -> (usize, usize, usize, usize) {
+        (
+            self.left,
+            self.top,
+            width.saturating_sub(self.left + self.right),
+            height.saturating_sub(self.top + self.bottom),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotation_from_degrees() {
+        assert_eq!(Rotation::from_degrees(0.0), Rotation::None);
+        assert_eq!(Rotation::from_degrees(90.0), Rotation::Cw90);
+        assert_eq!(Rotation::from_degrees(-90.0), Rotation::Cw270);
+        assert_eq!(Rotation::from_degrees(180.0), Rotation::Cw180);
+        assert_eq!(Rotation::from_degrees(-270.0), Rotation::Cw90);
+    }
+
+    #[test]
+    fn rotate_2x1() {
+        // 两个像素 A B，每个像素用一个字节区分
+        let src = [1, 1, 1, 2, 2, 2];
+        let mut dst = [0u8; 6];
+        rotate_bgr(Rotation::Cw90, 2, 1, &src, 6, &mut dst, 3);
+        // 顺时针 90 度后 A 在上，B 在下
+        assert_eq!(dst, [1, 1, 1, 2, 2, 2]);
+        rotate_bgr(Rotation::Cw270, 2, 1, &src, 6, &mut dst, 3);
+        assert_eq!(dst, [2, 2, 2, 1, 1, 1]);
+        rotate_bgr(Rotation::Cw180, 2, 1, &src, 6, &mut dst, 6);
+        assert_eq!(dst, [2, 2, 2, 1, 1, 1]);
+    }
+
+    #[test]
+    fn detect_letterbox() {
+        // 8x8，上下各 2 行黑边，左边 1 列黑边
+        let data: Vec<u8> = (0..8 * 8)
+            .flat_map(|i| {
+                let (x, y) = (i % 8, i / 8);
+                let v = if (2..6).contains(&y) && x >= 1 {
+                    200
+                } else {
+                    0
+                };
+                [v, v, v]
+            })
+            .collect();
+        let crop = Crop::detect(8, 8, 8 * 3, &data).unwrap();
+        let expected = Crop {
+            top: 2,
+            bottom: 2,
+            left: 1,
+            right: 0,
+        };
+        assert_eq!(crop, expected);
+        assert_eq!(crop.rect(8, 8), (1, 2, 7, 4));
+        assert!(Crop::detect(8, 8, 8 * 3, &[0; 8 * 8 * 3]).is_none());
+        assert!(crop.intersect(Crop::default()).is_empty());
+    }
+}
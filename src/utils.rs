@@ -1,6 +1,8 @@
 use anyhow::{Context, Result};
 use ffmpeg_next::Rational;
 use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 #[derive(Clone, Copy)]
 pub struct VideoDuration(pub Rational);
@@ -49,23 +51,62 @@ impl fmt::Display for VideoDuration {
     }
 }
 
-/// parse a string like "00:00:00.123" or "01:00:00.123" to Rational
+/// parse a string like "00:00:00.123", "01:30" or "90.5" to Rational.
+/// A leading "-" gives a negative duration.
 pub fn parse_duration(s: &str) -> Result<Rational> {
-    let mut parts = s.split(':');
-    let hours = parts.next().context("missing hour part")?.parse::<i64>()?;
-    let minutes = parts
-        .next()
-        .context("missing minute part")?
-        .parse::<i64>()?;
+    if let Some(s) = s.strip_prefix('-') {
+        return Ok(Rational::new(0, 1) - parse_duration(s)?);
+    }
+    let mut parts = s.rsplit(':');
     let seconds = parts
         .next()
         .context("missing seconds part")?
         .parse::<f64>()?;
+    let minutes = match parts.next() {
+        Some(m) => m.parse::<i64>()?,
+        None => 0,
+    };
+    let hours = match parts.next() {
+        Some(h) => h.parse::<i64>()?,
+        None => 0,
+    };
+    if parts.next().is_some() {
+        anyhow::bail!("too many parts in duration {}", s);
+    }
     let secs = (hours * 3600 + minutes * 60) as f64 + seconds;
     let r = Rational::from(secs);
     Ok(r)
 }
 
+/// 临时文件夹，drop 时删除
+pub(crate) struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new() -> Result<Self> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = std::env::temp_dir().join(format!(
+            "screenshot-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).context("create temp dir failed")?;
+        Ok(Self(dir))
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0)
+            .map_err(|e| warn!("remove temp dir {} failed: {e:#}", self.0.display()))
+            .ok();
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -88,5 +129,11 @@ mod tests {
 
         let s = "01:00:00.123";
         assert_eq!(parse_duration(s).unwrap(), Rational::new(3600123, 1000));
+
+        assert_eq!(parse_duration("01:30").unwrap(), Rational::new(90, 1));
+        assert_eq!(parse_duration("90").unwrap(), Rational::new(90, 1));
+        assert_eq!(parse_duration("-03:00").unwrap(), Rational::new(-180, 1));
+        assert!(parse_duration("1:00:00:00").is_err());
+        assert!(parse_duration("abc").is_err());
     }
 }
@@ -0,0 +1,188 @@
+//! 遍历输入文件夹，找出需要处理的视频
+
+use anyhow::{Context, Result};
+use globset::GlobSet;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::Config;
+
+/// 遍历的选项
+#[derive(Debug, Clone, Default)]
+pub struct WalkOptions {
+    /// 最多进入的子文件夹层数，`None` 表示不限制
+    pub max_depth: Option<usize>,
+    /// 是否跟随符号链接，默认跳过
+    pub follow_symlinks: bool,
+    /// 只处理相对路径匹配的文件，`None` 表示不限制
+    pub include: Option<GlobSet>,
+    /// 跳过相对路径匹配的文件和文件夹
+    pub exclude: GlobSet,
+}
+
+/// 遍历的结果，文件按路径排序
+#[derive(Default)]
+pub struct Walk {
+    /// 需要处理的文件和所在文件夹的配置
+    pub files: Vec<(PathBuf, Arc<Config>)>,
+    /// 无法读取的文件夹和配置文件
+    pub errors: Vec<(PathBuf, anyhow::Error)>,
+}
+
+/// 遍历 `root`，匹配 glob 并且 `filter` 返回 true 的文件才会处理。
+/// `filter` 的参数是文件和所在文件夹的配置
+pub fn walk(
+    root: &Path,
+    config: &Config,
+    options: &WalkOptions,
+    filter: impl Fn(&Path, &Config) -> bool,
+) -> Walk {
+    let mut walker = Walker {
+        root,
+        options,
+        filter,
+        visited: HashSet::new(),
+        result: Walk::default(),
+    };
+    walker.visit(root, config, 0);
+    walker.result
+}
+
+struct Walker<'a, F> {
+    root: &'a Path,
+    options: &'a WalkOptions,
+    filter: F,
+    /// 跟随符号链接时，防止循环
+    visited: HashSet<PathBuf>,
+    result: Walk,
+}
+
+impl<F: Fn(&Path, &Config) -> bool> Walker<'_, F> {
+    fn visit(&mut self, dir: &Path, config: &Config, depth: usize) {
+        if let Err(e) = self.try_visit(dir, config, depth) {
+            self.result.errors.push((dir.to_path_buf(), e));
+        }
+    }
+
+    fn try_visit(&mut self, dir: &Path, config: &Config, depth: usize) -> Result<()> {
+        if self.options.follow_symlinks {
+            let real = std::fs::canonicalize(dir).context("canonicalize dir failed")?;
+            if !self.visited.insert(real) {
+                debug!("skipping visited dir: {}", dir.display());
+                return Ok(());
+            }
+        }
+        let config = Arc::new(config.for_dir(dir)?);
+        let mut entries = dir
+            .read_dir()
+            .context("read dir failed")?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<std::io::Result<Vec<_>>>()
+            .context("read dir entry failed")?;
+        entries.sort();
+
+        let mut subdirs = vec![];
+        for path in entries {
+            let meta = match std::fs::symlink_metadata(&path) {
+                Ok(meta) if !meta.file_type().is_symlink() => meta,
+                Ok(_) if !self.options.follow_symlinks => {
+                    debug!("skipping symlink: {}", path.display());
+                    continue;
+                }
+                // 跟随符号链接
+                Ok(_) => match std::fs::metadata(&path) {
+                    Ok(meta) => meta,
+                    Err(e) => {
+                        let e = anyhow::Error::new(e).context("broken symlink");
+                        self.result.errors.push((path, e));
+                        continue;
+                    }
+                },
+                Err(e) => {
+                    let e = anyhow::Error::new(e).context("read metadata failed");
+                    self.result.errors.push((path, e));
+                    continue;
+                }
+            };
+            let relative = path.strip_prefix(self.root).unwrap_or(&path);
+            if self.options.exclude.is_match(relative) {
+                debug!("excluded: {}", path.display());
+            } else if meta.is_dir() {
+                subdirs.push(path);
+            } else if self.is_included(relative) && (self.filter)(&path, &config) {
+                self.result.files.push((path, Arc::clone(&config)));
+            } else {
+                debug!("skipping file: {}", path.display());
+            }
+        }
+
+        if self.options.max_depth.is_none_or(|max| depth < max) {
+            for subdir in subdirs {
+                self.visit(&subdir, &config, depth + 1);
+            }
+        }
+        Ok(())
+    }
+
+    fn is_included(&self, relative: &Path) -> bool {
+        match &self.options.include {
+            Some(include) => include.is_match(relative),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn globs(patterns: &[&str]) -> GlobSet {
+        let mut builder = globset::GlobSetBuilder::new();
+        for pattern in patterns {
+            builder.add(globset::Glob::new(pattern).unwrap());
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn walk_dir() {
+        let root = std::env::temp_dir().join(format!("screenshot-walk-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("b/c")).unwrap();
+        for f in ["b.mp4", "a.mp4", "a.txt", "b/a.mkv", "b/c/a.mp4"] {
+            std::fs::write(root.join(f), b"").unwrap();
+        }
+        let is_video = |p: &Path, _: &Config| p.extension().is_some_and(|ext| ext != "txt");
+        let names = |walk: Walk| -> Vec<_> {
+            walk.files
+                .into_iter()
+                .map(|(p, _)| p.strip_prefix(&root).unwrap().to_path_buf())
+                .collect()
+        };
+
+        let all = walk(&root, &Config::default(), &WalkOptions::default(), is_video);
+        assert!(all.errors.is_empty());
+        assert_eq!(
+            names(all),
+            ["a.mp4", "b.mp4", "b/a.mkv", "b/c/a.mp4"].map(PathBuf::from)
+        );
+        let options = WalkOptions {
+            max_depth: Some(1),
+            ..Default::default()
+        };
+        let shallow = walk(&root, &Config::default(), &options, is_video);
+        assert_eq!(
+            names(shallow),
+            ["a.mp4", "b.mp4", "b/a.mkv"].map(PathBuf::from)
+        );
+        let options = WalkOptions {
+            include: Some(globs(&["**/a.*"])),
+            exclude: globs(&["b/c"]),
+            ..Default::default()
+        };
+        let filtered = walk(&root, &Config::default(), &options, is_video);
+        assert_eq!(names(filtered), ["a.mp4", "b/a.mkv"].map(PathBuf::from));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,61 @@
+//! 只通过公开的 API 使用 `screenshot`
+
+use screenshot::{
+    ContactSheet, ExtractOptions, FrameSelection, Rational, RenderOptions, SheetLayout,
+};
+
+#[test]
+fn default_layout() {
+    let layout = SheetLayout::default();
+    assert_eq!(
+        (layout.rows, layout.cols, layout.width, layout.space),
+        (5, 3, 2048, 10)
+    );
+    assert!(layout.auto_flip);
+    assert_eq!(layout.num_of_frames(), 15);
+    assert_eq!(RenderOptions::default().ext, "jpg");
+
+    let sheet = ContactSheet::new();
+    assert_eq!(sheet.layout, layout);
+    assert_eq!(sheet.extract.selection, FrameSelection::Uniform);
+}
+
+#[test]
+fn builder() {
+    let sheet = ContactSheet::new()
+        .rows(4)
+        .cols(6)
+        .width(1024)
+        .space(4)
+        .auto_flip(false)
+        .ext("png")
+        .selection(FrameSelection::SceneChange { candidates: 5 })
+        .range(Some(Rational::new(90, 1)), None)
+        .accurate_seek(true)
+        .parallel(2);
+    assert_eq!(
+        sheet.layout,
+        SheetLayout {
+            rows: 4,
+            cols: 6,
+            width: 1024,
+            space: 4,
+            auto_flip: false,
+        }
+    );
+    assert_eq!(sheet.options.ext, "png");
+    assert_eq!(
+        sheet.extract.selection,
+        FrameSelection::SceneChange { candidates: 5 }
+    );
+    assert_eq!(sheet.extract.start, Some(Rational::new(90, 1)));
+    assert!(sheet.extract.accurate_seek);
+    assert_eq!(sheet.extract.parallel, 2);
+
+    // 指定时间点时，行数随时间点数量变化
+    let sheet = sheet.extract_options(ExtractOptions {
+        timestamps: (1..=7).map(|t| Rational::new(t, 1)).collect(),
+        ..Default::default()
+    });
+    assert_eq!(sheet.effective_layout().rows, 2);
+}